@@ -0,0 +1,243 @@
+use crate::compression;
+use crate::error::IntergeneError;
+use crate::gff::GffEntry;
+use crate::hierarchy::FeatureTree;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::prelude::*;
+
+#[derive(Debug)]
+pub enum BedErrors {
+    /// The line has less than 4 columns
+    BedColumnMismatch(String),
+    /// The start (column 2) or end (column 3) of the line is not an integer
+    InvalidCoordinate(String, usize),
+}
+
+impl BedErrors {
+    /// Turns the error into an `IntergeneError::Parse` naming the file and line it occurred in
+    pub fn in_file(self, path: &str, line: usize) -> IntergeneError {
+        match self {
+            BedErrors::BedColumnMismatch(_) => {
+                IntergeneError::parse(path, line, None, "expected at least 4 columns")
+            }
+            BedErrors::InvalidCoordinate(_, column) => {
+                IntergeneError::parse(path, line, Some(column), "the coordinate is not an integer")
+            }
+        }
+    }
+}
+
+/// A region of a .bed file, i.e chromosome, start, end and name of the region
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+pub struct BedRegion {
+    pub chromosome: String,
+    pub start: i64,
+    pub end: i64,
+    pub name: String,
+}
+
+impl BedRegion {
+    /// Create a region from the (tab separated) columns of a single bed line
+    /// Only the first 4 columns are used, so BED6/BED12 files can be read as well
+    pub fn from_fields(fields: &[&str]) -> Result<BedRegion, BedErrors> {
+        let line = fields.join("\t");
+        match fields.len() {
+            4.. => {
+                let start = fields[1]
+                    .parse::<i64>()
+                    .map_err(|_| BedErrors::InvalidCoordinate(line.clone(), 2))?;
+                let end = fields[2]
+                    .parse::<i64>()
+                    .map_err(|_| BedErrors::InvalidCoordinate(line.clone(), 3))?;
+                Ok(BedRegion {
+                    chromosome: fields[0].to_string(),
+                    start,
+                    end,
+                    name: fields[3].to_string(),
+                })
+            }
+            _ => Err(BedErrors::BedColumnMismatch(line)),
+        }
+    }
+}
+
+/// Read a .bed file that contains choromome, start, end and name of region. Should only be read once
+pub fn read_bed(bedfile: &str) -> Result<Vec<BedRegion>, IntergeneError> {
+    let reader = compression::open(bedfile)?;
+    let mut bed_regions: Vec<BedRegion> = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| IntergeneError::io(bedfile, e))?;
+        let split_line = line.split('\t').collect::<Vec<&str>>();
+        bed_regions
+            .push(BedRegion::from_fields(&split_line).map_err(|e| e.in_file(bedfile, idx + 1))?);
+    }
+    Ok(bed_regions)
+}
+
+/// The flavour of BED file to write
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BedFormat {
+    /// chrom, start, end, name, score, strand
+    Bed6,
+    /// BED6 + thickStart, thickEnd, itemRgb and the blocks, i.e the exons of transcripts
+    Bed12,
+}
+
+/// Writes the entries of the given type as a BED file, i.e with 0-based, half-open coordinates
+/// The name is taken from the `ID` attribute (or `Name` if there is no ID)
+/// Entries spanning the origin of a circular sequence (i.e ending past its length in `lengths`)
+/// are split into a record up to the end and one from the start of the sequence
+/// The records are sorted by chromosome and start (as `sort -k1,1 -k2,2n`), e.g for depth-add-name
+/// In BED12 the blocks are the exon children of an entry (via ID/Parent) and the thick part spans
+/// its CDS children. Entries without exons are a single block. Entries without CDS are entirely
+/// thick, unless they have exons (i.e are non-coding transcripts)
+pub fn write_bed_from_vec(
+    entry_type: &str,
+    gff_entries: &[GffEntry],
+    lengths: &HashMap<String, i64>,
+    filename: &str,
+    format: BedFormat,
+    bgzip: bool,
+) -> Result<(), IntergeneError> {
+    let tree = FeatureTree::new(gff_entries);
+    // The records as (chromosome, chromStart, line)
+    let mut records: Vec<(&str, i64, String)> = Vec::new();
+    for entry in gff_entries.iter().filter(|e| e.r#type == entry_type) {
+        let name = entry
+            .attributes
+            .get_first("ID")
+            .or_else(|| entry.attributes.get_first("Name"))
+            .unwrap_or(".");
+        let score = entry.score.parse::<f64>().map_or(0, |s| s as i64);
+
+        // The exons and CDS of the entry in GFF coordinates, sorted by start
+        let children = |r#type: &str| -> Vec<(i64, i64)> {
+            let mut children: Vec<(i64, i64)> = match entry.attributes.get_first("ID") {
+                Some(id) => tree
+                    .children_of_type(id, r#type)
+                    .iter()
+                    .map(|child| (child.start, child.end))
+                    .collect(),
+                None => Vec::new(),
+            };
+            children.sort_unstable();
+            children
+        };
+        let exons = children("exon");
+        let cds = children("CDS");
+        let thick = match (cds.first(), cds.iter().map(|c| c.1).max()) {
+            (Some(first), Some(last)) => Some((first.0, last)),
+            _ if exons.is_empty() => Some((entry.start, entry.end)),
+            _ => None,
+        };
+        let blocks = if exons.is_empty() {
+            vec![(entry.start, entry.end)]
+        } else {
+            exons
+        };
+
+        // The parts (start, end, shift) before and after the origin
+        let parts = match lengths.get(&entry.seqid) {
+            Some(&length) if entry.end > length => {
+                vec![(entry.start, length, 0), (length + 1, entry.end, length)]
+            }
+            _ => vec![(entry.start, entry.end, 0)],
+        };
+        for (start, end, shift) in parts {
+            let chrom_start = start - shift - 1;
+            let chrom_end = end - shift;
+            let mut to_write = String::new();
+            let _ = write!(
+                to_write,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                entry.seqid, chrom_start, chrom_end, name, score, entry.strand
+            );
+            if format == BedFormat::Bed12 {
+                // Clipped to the part, in BED coordinates
+                let clip = |(s, e): (i64, i64)| {
+                    let (s, e) = (s.max(start), e.min(end));
+                    (s <= e).then_some((s - shift - 1, e - shift))
+                };
+                let mut part_blocks: Vec<(i64, i64)> =
+                    blocks.iter().filter_map(|block| clip(*block)).collect();
+                if part_blocks.is_empty() {
+                    part_blocks.push((chrom_start, chrom_end));
+                }
+                let (thick_start, thick_end) =
+                    thick.and_then(clip).unwrap_or((chrom_start, chrom_start));
+                let _ = write!(
+                    to_write,
+                    "\t{}\t{}\t0\t{}\t",
+                    thick_start,
+                    thick_end,
+                    part_blocks.len()
+                );
+                for (s, e) in &part_blocks {
+                    let _ = write!(to_write, "{},", e - s);
+                }
+                to_write.push('\t');
+                for (s, _) in &part_blocks {
+                    let _ = write!(to_write, "{},", s - chrom_start);
+                }
+            }
+            to_write.push('\n');
+            records.push((entry.seqid.as_str(), chrom_start, to_write));
+        }
+    }
+    records.sort_by(|a, b| a.0.cmp(b.0).then(a.1.cmp(&b.1)));
+    let to_write: String = records.into_iter().map(|(_, _, line)| line).collect();
+    compression::write_file(filename, &to_write, bgzip)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gff::tests::{entry, TempDir};
+    use std::fs;
+
+    #[test]
+    fn writes_zero_based_records_split_at_the_origin() {
+        let entries = vec![
+            entry("intergenic", 5, 10, ".", "ID=IGR_1"),
+            entry("intergenic", 291, 310, ".", "ID=IGR_2"),
+            entry("mRNA", 101, 200, "-", "ID=t1"),
+            entry("exon", 151, 200, "-", "Parent=t1"),
+            entry("exon", 101, 120, "-", "Parent=t1"),
+            entry("CDS", 111, 120, "-", "Parent=t1"),
+            entry("CDS", 151, 180, "-", "Parent=t1"),
+            entry("ncRNA", 201, 250, "+", "ID=nc1"),
+            entry("exon", 201, 210, "+", "Parent=nc1"),
+            entry("exon", 241, 250, "+", "Parent=nc1"),
+        ];
+        let lengths: HashMap<String, i64> = [("chr1".to_string(), 300)].into_iter().collect();
+        let dir = TempDir::new("bed");
+        let file = &dir.file("test.bed");
+        let written = |entry_type: &str, format: BedFormat| {
+            write_bed_from_vec(entry_type, &entries, &lengths, file, format, false).unwrap();
+            fs::read_to_string(file).unwrap()
+        };
+
+        assert_eq!(
+            written("intergenic", BedFormat::Bed6),
+            "chr1\t0\t10\tIGR_2\t0\t.\n\
+             chr1\t4\t10\tIGR_1\t0\t.\n\
+             chr1\t290\t300\tIGR_2\t0\t.\n"
+        );
+        assert_eq!(
+            written("intergenic", BedFormat::Bed12),
+            "chr1\t0\t10\tIGR_2\t0\t.\t0\t10\t0\t1\t10,\t0,\n\
+             chr1\t4\t10\tIGR_1\t0\t.\t4\t10\t0\t1\t6,\t0,\n\
+             chr1\t290\t300\tIGR_2\t0\t.\t290\t300\t0\t1\t10,\t0,\n"
+        );
+        assert_eq!(
+            written("mRNA", BedFormat::Bed12),
+            "chr1\t100\t200\tt1\t0\t-\t110\t180\t0\t2\t20,50,\t0,50,\n"
+        );
+        assert_eq!(
+            written("ncRNA", BedFormat::Bed12),
+            "chr1\t200\t250\tnc1\t0\t+\t200\t200\t0\t2\t10,10,\t0,40,\n"
+        );
+    }
+}
@@ -1,28 +1,70 @@
-use csv::Reader;
-use rayon::prelude::*;
-use std::{env, error::Error, fs::File, io};
+use clap::{App, Arg};
+use intergene_things::bed::BedRegion;
+use intergene_things::compression;
+use intergene_things::depth::DepthInfo;
+use intergene_things::error::IntergeneError;
+use std::io;
 
-type BRecord = (String, u64, u64, String);
-type DRecord = (String, u64, u32);
+fn main() {
+    let matches = App::new("Adding names to samtools depth output from bedfiles (streaming)")
+        .version("0.1")
+        .about(
+            "Adds the names of the regions in a bed file to the output of \"samtools depth\", streaming both files instead of reading them into memory. Both files have to be sorted by position",
+        )
+        .arg(
+            Arg::with_name("depth")
+                .short('d')
+                .long("depth")
+                .value_name("depth")
+                .help("Depth file from \"samtools depth\" command to add names to, \"-\" for stdin")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("bedfile")
+                .short('b')
+                .long("bed")
+                .value_name("bedfile")
+                .help("bed file containing the start and end values, as well as the names of the regions")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short('o')
+                .long("output")
+                .value_name("output")
+                .help("The output file, written bgzip compressed if it ends in .gz, \"-\" for stdout")
+                .takes_value(true)
+                .default_value("-"),
+        )
+        .get_matches();
 
-// enum Recs {
-//     BRecord,
-//     DRecord,
-// }
+    let d_file = matches.value_of("depth").unwrap();
+    let b_file = matches.value_of("bedfile").unwrap();
+    let output = matches.value_of("output").unwrap();
+    if let Err(e) = add_name(d_file, b_file, output) {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let d_file = &args[1];
-    let b_file = &args[2];
-    add_name(d_file.to_string(), b_file.to_string()).unwrap();
+/// Turns an error of the csv reader/writer into an `IntergeneError` for the given file
+fn csv_error(path: &str, e: csv::Error) -> IntergeneError {
+    match e.position() {
+        Some(position) => {
+            IntergeneError::parse(path, position.line() as usize, None, &e.to_string())
+        }
+        None => IntergeneError::io(path, io::Error::from(e)),
+    }
 }
 
-fn add_name(d_file: String, b_file: String) -> Result<(), Box<dyn Error>> {
-    let d_file = File::open(d_file)?;
-    let b_file = File::open(b_file)?;
+fn add_name(d_path: &str, b_path: &str, output: &str) -> Result<(), IntergeneError> {
+    let d_file = compression::open(d_path)?;
+    let b_file = compression::open(b_path)?;
     let mut wtr = csv::WriterBuilder::new()
         .delimiter(b'\t')
-        .from_writer(io::stdout());
+        .from_writer(compression::create(output, false)?);
     let mut d_rdr = csv::ReaderBuilder::new()
         .has_headers(false)
         .delimiter(b'\t')
@@ -34,18 +76,30 @@ fn add_name(d_file: String, b_file: String) -> Result<(), Box<dyn Error>> {
 
     // parallelise the code below using rayon
 
-    for entry in b_rdr.deserialize() {
-        let bed_entry: BRecord = entry?;
-        let end = bed_entry.2;
-        for result in d_rdr.deserialize() {
-            let record: DRecord = result?;
-            let basepos = record.1;
-            if basepos > end {
+    for entry in b_rdr.records() {
+        let entry = entry.map_err(|e| csv_error(b_path, e))?;
+        let line = entry.position().map_or(0, |p| p.line() as usize);
+        let bed_entry = BedRegion::from_fields(&entry.iter().collect::<Vec<&str>>())
+            .map_err(|e| e.in_file(b_path, line))?;
+        for result in d_rdr.records() {
+            let result = result.map_err(|e| csv_error(d_path, e))?;
+            let line = result.position().map_or(0, |p| p.line() as usize);
+            let record = DepthInfo::from_fields(&result.iter().collect::<Vec<&str>>())
+                .map_err(|e| e.in_file(d_path, line))?;
+            if record.basenumber > bed_entry.end {
                 break;
             }
-            wtr.serialize((&record.0, &record.1, &record.2, &bed_entry.3))?;
+            wtr.serialize((
+                &record.chromosome,
+                &record.basenumber,
+                &record.reads,
+                &bed_entry.name,
+            ))
+            .map_err(|e| csv_error(output, e))?;
         }
     }
-    wtr.flush()?;
-    Ok(())
+    wtr.into_inner()
+        .map_err(|e| io::Error::new(e.error().kind(), e.error().to_string()))
+        .and_then(|writer| writer.finish())
+        .map_err(|e| IntergeneError::io(output, e))
 }
@@ -1,6 +1,10 @@
 #![allow(unused)]
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use indicatif::ProgressBar;
+use intergene_things::bed::{read_bed, BedRegion};
+use intergene_things::compression;
+use intergene_things::depth::{add_name_to_depth, read_depths, DepthInfo};
+use intergene_things::error::IntergeneError;
 use rayon::prelude::*;
 use std::fmt::Write as _;
 use std::fs::File;
@@ -17,33 +21,11 @@ use std::time::Duration;
 //       add length to var, then start from that +1 until the next region length,
 //       assign all of these to the next index of the bed names
 
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
-struct BedRegion {
-    chromosome: String,
-    start: i64,
-    end: i64,
-    name: String,
-}
-
 struct OutputType {
     file: BufWriter<File>,
     stdout: bool,
 }
 
-#[derive(Debug)]
-struct DepthInfo {
-    chromosome: String,
-    basenumber: i64,
-    reads: i64,
-    name: String,
-}
-
-impl DepthInfo {
-    fn add_name(&mut self, name: &str) {
-        self.name.push_str(name);
-    }
-}
-
 fn main() {
     let matches = App::new("Adding names to samtools depth output from bedfiles")
         .version("0.1")
@@ -57,7 +39,7 @@ It also assumes that both the bed file and the depth file are sorted by start po
                 .short('d')
                 .long("depth")
                 .value_name("depth")
-                .help("Depth file from \"samtools depth\" command to add names to")
+                .help("Depth file from \"samtools depth\" command to add names to, \"-\" for stdin (only if it is the only depth file)")
                 .takes_value(true)
                 .multiple(true)
                 .required(true),
@@ -76,9 +58,15 @@ It also assumes that both the bed file and the depth file are sorted by start po
                 .short('o')
                 .long("output")
                 .value_name("output")
-                .help("Either define the output file name or the output will be written to stdout")
+                .help("Either define the output file name (\".depthn\" is appended) or the output will be written to stdout, also with \"-\"")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("bgzip")
+                .long("bgzip")
+                .help("Write bgzip compressed output (.depthn.gz files)")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("threads")
                 .short('t')
@@ -89,156 +77,113 @@ It also assumes that both the bed file and the depth file are sorted by start po
         )
         .get_matches();
 
-    let bedfile = matches.value_of("bedfile").unwrap();
+    if let Err(e) = run(&matches) {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), IntergeneError> {
+    let missing = |name: &str| IntergeneError::InvalidArgument {
+        name: name.to_string(),
+        value: String::new(),
+    };
+    let bedfile = matches.value_of("bedfile").ok_or_else(|| missing("bed"))?;
     let depthfiles: Vec<String> = matches
         .get_many::<String>("depth")
-        .expect("Womw")
+        .ok_or_else(|| missing("depth"))?
         .map(|s| s.to_string())
         .collect();
 
     // Read bed regions once, so if there are more than one depth file to look at, no need to read the bed file again
-    let bed_regions: Vec<BedRegion> = read_bed(bedfile);
+    let bed_regions: Vec<BedRegion> = read_bed(bedfile)?;
+
+    let bgzip = matches.is_present("bgzip");
 
     // Multithread configuration
-    let n_threads: usize = matches
-        .get_one("threads")
-        .unwrap_or(&"3".to_string())
-        .parse::<usize>()
-        .unwrap();
+    let threads = matches.value_of("threads").unwrap_or("3");
+    let invalid_threads = || IntergeneError::InvalidArgument {
+        name: "threads".to_string(),
+        value: threads.to_string(),
+    };
+    let n_threads: usize = threads.parse::<usize>().map_err(|_| invalid_threads())?;
     rayon::ThreadPoolBuilder::new()
-        .num_threads(n_threads.to_owned() as usize)
+        .num_threads(n_threads)
         .build_global()
-        .unwrap();
+        .map_err(|_| invalid_threads())?;
+
+    // Every depth file is written next to itself, which stdin has no place for
+    if depthfiles.len() > 1 && depthfiles.iter().any(|d| d == "-") {
+        return Err(IntergeneError::InvalidArgument {
+            name: "depth".to_string(),
+            value: "-".to_string(),
+        });
+    }
 
     if depthfiles.len() > 1 {
         // If more than one file given, automatically output to different files
         // NOTE: This is the multithreaded version using rayon
-        depthfiles.par_iter().for_each(|i| {
-            let mut depths: Vec<DepthInfo> = read_depths(i);
-            add_name_to_depth(depths.as_mut(), &bed_regions);
-            write_depthn(&depths, i, false);
-        });
+        depthfiles.par_iter().try_for_each(|i| {
+            let mut depths: Vec<DepthInfo> = read_depths_with_progress(i)?;
+            add_name_with_progress(depths.as_mut(), &bed_regions);
+            write_depthn(&depths, i, false, bgzip)
+        })
     } else {
         // Only one depth file to look at and write/print, stdout or outputfile if given
-        let mut depths = read_depths(&depthfiles[0]);
-        add_name_to_depth(&mut depths, &bed_regions);
-        if matches.is_present("output") {
-            let o = matches.value_of("output").unwrap();
-            write_depthn(&depths, o, false);
-        } else {
-            write_depthn(&depths, "", true);
+        let mut depths = read_depths_with_progress(&depthfiles[0])?;
+        add_name_with_progress(&mut depths, &bed_regions);
+        match matches.value_of("output") {
+            Some("-") | None => write_depthn(&depths, "", true, bgzip),
+            Some(o) => write_depthn(&depths, o, false, bgzip),
         }
     }
 }
 
-/// Writes the depth info to either stdout or a file
-fn write_depthn(depths: &Vec<DepthInfo>, filename: &str, stdout: bool) {
+/// Writes the depth info to either stdout or a file, bgzip compressed if `bgzip` is set
+fn write_depthn(
+    depths: &[DepthInfo],
+    filename: &str,
+    stdout: bool,
+    bgzip: bool,
+) -> Result<(), IntergeneError> {
     // Writes the depth file along with the name column
-    let mut writer = BufWriter::new(File::create(&format!("{}.depthn", filename)).unwrap());
-    let mut stdout_writer = BufWriter::new(std::io::stdout());
+    let extension = if bgzip { "depthn.gz" } else { "depthn" };
+    let path = if stdout {
+        "-".to_string()
+    } else {
+        format!("{}.{}", filename, extension)
+    };
+    let mut writer = compression::create(&path, bgzip)?;
     for d in depths {
-        if stdout {
-            // println!(
-            //     "{}\t{}\t{}\t{}",
-            //     d.chromosome, d.basenumber, d.reads, d.name
-            // );
-            writeln!(
-                stdout_writer,
-                "{}\t{}\t{}\t{}",
-                d.chromosome, d.basenumber, d.reads, d.name
-            )
-            .unwrap();
-        } else {
-            writeln!(
-                writer,
-                "{}\t{}\t{}\t{}",
-                d.chromosome, d.basenumber, d.reads, d.name
-            )
-            .unwrap();
-        }
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            d.chromosome, d.basenumber, d.reads, d.name
+        )
+        .map_err(|e| IntergeneError::io(&path, e))?;
     }
-    println!("Wrote {f} to file {f}.depthn", f = filename);
+    writer.finish().map_err(|e| IntergeneError::io(&path, e))?;
+    if !stdout {
+        eprintln!("Wrote {f} to file {f}.{e}", f = filename, e = extension);
+    }
+    Ok(())
 }
 
-/// Adds the name of the region to the depth file, based on the bed file
-/// input: vector of depth info, vector of bed regions
-/// output: mutated original vector of depth info with the name of the region added
-fn add_name_to_depth(depths: &mut Vec<DepthInfo>, bed_regions: &Vec<BedRegion>) {
+/// Adds the region names to the depths (see `add_name_to_depth`), showing a spinner while doing so
+fn add_name_with_progress(depths: &mut [DepthInfo], bed_regions: &[BedRegion]) {
     let bar = ProgressBar::new_spinner();
     bar.enable_steady_tick(Duration::from_millis(10));
     bar.set_message("Modifying Depth files...");
-    let mut idx = 0;
-    for depth in depths {
-        if depth.basenumber <= bed_regions[idx].end {
-            depth.name = bed_regions[idx].name.clone();
-        } else {
-            if idx < bed_regions.len() - 1 {
-                idx += 1;
-            }
-            depth.name = bed_regions[idx].name.clone();
-        }
-    }
+    add_name_to_depth(depths, bed_regions);
     bar.finish();
 }
 
-/// Read a .bed file that contains choromome, start, end and name of region. Should only be read once
-fn read_bed(bedfile: &str) -> Vec<BedRegion> {
-    let content = File::open(bedfile).expect("Unable to open file");
-    let reader = BufReader::new(content);
-    let mut bed_regions: Vec<BedRegion> = Vec::new();
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let mut split_line = line.split('\t').collect::<Vec<&str>>();
-        match split_line.len() {
-            4 => {
-                let chromosome = split_line[0].to_string();
-                let start = split_line[1]
-                    .parse::<i64>()
-                    .unwrap_or_else(|_| panic!("Unable to parse start value {}", line));
-                let end = split_line[2]
-                    .parse::<i64>()
-                    .unwrap_or_else(|_| panic!("Unable to parse end value {}", line));
-                let name = split_line[3].to_string();
-                bed_regions.push(BedRegion {
-                    chromosome,
-                    start,
-                    end,
-                    name,
-                });
-            }
-            _ => {
-                println!(
-                    "Error in bed file; incorrectly structured at line: {}. ",
-                    line
-                );
-                std::process::exit(1);
-            }
-        }
-    }
-    bed_regions
-}
-
-/// Read a .depth file from the output of the samtools depth command
-fn read_depths(filename: &str) -> Vec<DepthInfo> {
-    let content = File::open(filename).expect("Unable to open file");
-    let mut depths: Vec<DepthInfo> = Vec::new();
-    let reader = BufReader::new(content);
+/// Reads a depth file (see `read_depths`), showing a spinner while doing so
+fn read_depths_with_progress(filename: &str) -> Result<Vec<DepthInfo>, IntergeneError> {
     let bar = ProgressBar::new_spinner();
     bar.enable_steady_tick(Duration::from_millis(10));
     bar.set_message("Reading Depth files...");
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let mut split_line = line.split('\t');
-        let chromosome = split_line.next().unwrap();
-        let position = split_line.next().unwrap().parse::<i64>().unwrap();
-        let depth = split_line.next().unwrap().parse::<i64>().unwrap();
-        depths.push(DepthInfo {
-            chromosome: chromosome.to_string(),
-            basenumber: position,
-            reads: depth,
-            name: "".to_string(),
-        });
-    }
+    let depths = read_depths(filename);
     bar.finish();
     depths
 }
@@ -1,61 +1,27 @@
 // Read file
 #![allow(unused)]
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
+use intergene_things::bed::{write_bed_from_vec, BedFormat};
+use intergene_things::compression::{is_bgzf, is_compressed};
+use intergene_things::error::IntergeneError;
+use intergene_things::fasta::{
+    add_seq_to_entries, parse_fasta, read_sequence_lengths, write_fasta_to_file, IndexedFasta,
+    SeqSource, DEFAULT_HEADER_TEMPLATE,
+};
+use intergene_things::gff::{
+    get_valid_types, group_by_seqid, is_circular, parse_gff, write_gff_from_vec,
+    write_gtf_from_vec, AttributeFilter, GffEntry, GffFormat,
+};
+use intergene_things::hierarchy::FeatureTree;
+use intergene_things::intervals::{
+    create_flanking_entries, create_intergenic_entries, filter_by_length, get_intergenic_regions,
+    trim_regions, FlankType, GenicTypes, IntergenicRegion, DEFAULT_ID_TEMPLATE,
+};
+use intergene_things::translate::{write_protein_fasta, GeneticCode};
+use intergene_things::validate::{validate_entries, write_report, Finding};
 use rayon::prelude::*;
-use std::fmt::Write as _;
-use std::fs;
-use std::io::Error;
-// use std::fs::File;
-// use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::collections::HashMap;
 //
-// TODO: Strand-specific intergene finding. This is because the start/stop are switchedin location dependingon the strand
-
-#[derive(Debug)]
-enum GFFErrors {
-    FileNotFound,
-    GFFColumnMismatch,
-}
-
-#[derive(Debug)]
-enum FastaErrors {
-    FileNotFound,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, PartialEq, Eq, Ord, PartialOrd, Clone)]
-struct GffEntry {
-    seqid: String,
-    source: String,
-    r#type: String,
-    start: i64,
-    end: i64,
-    score: String,
-    strand: String,
-    phase: String,
-    attributes: String,
-    seq: String,
-}
-
-impl GffEntry {
-    fn add_seq(&mut self, seq: String) {
-        self.seq = seq;
-    }
-}
-
-struct Gff {
-    header: String,
-    entries: Vec<GffEntry>,
-}
-
-#[allow(dead_code)]
-struct Seq {
-    header: String,
-    seq: String,
-}
-
-// TODO: If given a fasta, also extract sequences from the fasta, if not don't, just create a .gff file including the intergenic regions
-// TODO: allow for adding a padding/buffer option in which there either has to be a min length for IGR's, or the distance between the IGR and the previous/next
-// gene has to at least be that minimum distance apart i.e GENE ---40nt--> IGR ---40nt---> Gene
 
 fn main() {
     let matches = App::new("Finds intergenic regions, creates FASTA and GFF file")
@@ -69,10 +35,19 @@ fn main() {
                 .short('i')
                 .long("input")
                 .value_name("input")
-                .help("Input GFF file to find the intergenic regions from")
+                .help("Input GFF3 or GTF (.gtf) file to find the intergenic regions from, \"-\" for stdin")
                 .takes_value(true)
                 .required(true),
         )
+        .arg(
+            Arg::with_name("input_format")
+                .long("input_format")
+                .value_name("input_format")
+                .help("Read the input as GFF3 (\"gff\") or GTF (\"gtf\"), e.g for GTF from stdin. Defaults to GTF for .gtf/.gff2 files and GFF3 otherwise")
+                .takes_value(true)
+                .possible_values(["gff", "gtf"])
+                .required(false)
+        )
         .arg(
             Arg::with_name("fasta")
                 .short('f')
@@ -82,6 +57,15 @@ fn main() {
                 .takes_value(true)
                 .required(false)
         )
+        .arg(
+            Arg::with_name("lengths")
+                .short('l')
+                .long("lengths")
+                .value_name("lengths")
+                .help("A .fai index or chrom.sizes file giving the sequence lengths, used when no FASTA is given")
+                .takes_value(true)
+                .required(false)
+        )
         .arg(
             Arg::with_name("types")
                 .short('t')
@@ -93,6 +77,54 @@ fn main() {
                 .value_delimiter(',')
                 .default_value("intergenic")
         )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .value_name("filter")
+                .help("Only extract entries whose attribute has the given value, given as key=value (e.g gene_biotype=rRNA). Can be given multiple times, all filters have to match")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("header_template")
+                .long("header_template")
+                .value_name("header_template")
+                .help("Template for the FASTA headers, using the GFF columns ({seqid}, {start}, {end}, {strand}, ...), {length}, {attributes} or any attribute, e.g \"{ID} {locus_tag} {seqid}:{start}-{end}({strand})\". Defaults to \"{attributes} length: {length}\"")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("introns")
+                .long("introns")
+                .help("Also add intron entries, i.e the gaps between the exons of each transcript (linked via Parent), which can be extracted with \"--types intron\"")
+                .required(false)
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("spliced")
+                .long("spliced")
+                .value_name("spliced")
+                .help("Extract entries (e.g mRNA) as the concatenation of their exon or CDS children (via ID/Parent, or transcript_id in GTF), giving cDNA or CDS sequences instead of the genomic span")
+                .takes_value(true)
+                .possible_values(["exon", "CDS"])
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("translate")
+                .long("translate")
+                .help("Also translate the extracted entries (e.g CDS), writing a protein FASTA ({type}.faa). Uses the phase column and the transl_table attribute")
+                .required(false)
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("table")
+                .long("table")
+                .value_name("table")
+                .help("The NCBI genetic code to translate with, if an entry has no transl_table attribute (11 for bacteria, 4 for mycoplasma)")
+                .default_value("11")
+                .takes_value(true)
+        )
         .arg(
             Arg::with_name("strandedness")
                 .short('s')
@@ -102,6 +134,34 @@ fn main() {
                 .required(false)
                 .takes_value(false)
         )
+        .arg(
+            Arg::with_name("genic_types")
+                .long("genic-types")
+                .value_name("genic_types")
+                .help("Only these entry types (separated by commas) count as genic, e.g \"gene,pseudogene\". Defaults to all types apart from region and sequence_feature")
+                .takes_value(true)
+                .multiple(true)
+                .value_delimiter(',')
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("ignore_types")
+                .long("ignore-types")
+                .value_name("ignore_types")
+                .help("Entry types (separated by commas) that never count as genic, e.g \"ncRNA,riboswitch\"")
+                .takes_value(true)
+                .multiple(true)
+                .value_delimiter(',')
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("circular")
+                .short('c')
+                .long("circular")
+                .help("Treat all sequences as circular, so the first and last intergenic region are one spanning the origin. Sequences with a region entry with Is_circular=true are always treated as circular")
+                .required(false)
+                .takes_value(false)
+        )
         .arg(
             Arg::with_name("min_distance")
                 .short('d')
@@ -112,352 +172,568 @@ fn main() {
                 .default_missing_value("3")
                 .takes_value(true)
         )
+        .arg(
+            Arg::with_name("id_template")
+                .long("id_template")
+                .value_name("id_template")
+                .help("Template for the IDs of the intergenic regions, using {seqid}, {start}, {end}, {strand} and the locus tags of the flanking genes {left}/{right}, e.g \"IGR_{left}_{right}\". Defaults to \"IGR_{seqid}_{start}_{end}\" (plus \"_{strand}\" with --strandedness)")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("trim")
+                .long("trim")
+                .value_name("trim")
+                .help("Shrink the intergenic regions by this many nt on each side that borders a gene, e.g to leave out RBS/terminators")
+                .default_value("0")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("extend")
+                .long("extend")
+                .value_name("extend")
+                .help("Extend the extracted sequence of the intergenic regions by this many nt into the flanking genes, for context")
+                .default_value("0")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("min_length")
+                .long("min_length")
+                .value_name("min_length")
+                .help("The minimum length of an intergenic region (after trimming) to be reported")
+                .default_value("1")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("max_length")
+                .long("max_length")
+                .value_name("max_length")
+                .help("The maximum length of an intergenic region (after trimming) to be reported")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("flank_length")
+                .long("flank_length")
+                .value_name("flank_length")
+                .help("Also add features of up to this many bases flanking the genes next to each intergenic region, clipped to the region (see --flank_type)")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("flank_type")
+                .long("flank_type")
+                .value_name("flank_type")
+                .help("The flanking features to add: \"utr\" for five_prime_UTR/three_prime_UTR up- and downstream of each gene or \"upstream\" for an upstream_region (e.g promoter) only")
+                .default_value("upstream")
+                .possible_values(["utr", "upstream"])
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("output_format")
+                .long("output_format")
+                .value_name("output_format")
+                .help("Write the GFF with the intergenic regions as GFF3 (\"gff\") or GTF (\"gtf\"), defaults to the format of the input")
+                .takes_value(true)
+                .possible_values(["gff", "gtf"])
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("bed")
+                .long("bed")
+                .help("Also write the entries of each type as a BED6 file, e.g for \"samtools depth -b\" and depth-add-name")
+                .required(false)
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("bed12")
+                .long("bed12")
+                .help("Like --bed, but write BED12 instead of BED6")
+                .required(false)
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("validate")
+                .long("validate")
+                .help("Only check the GFF against the FASTA (known seqids, coordinates within the sequences, CDS lengths and start/stop codons, non-ACGTN bases) and write the issues to validation.tsv in the output folder. Exits with 1 if there are any")
+                .required(false)
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("bgzip")
+                .long("bgzip")
+                .help("Write bgzip compressed output files (with a .gz extension)")
+                .required(false)
+                .takes_value(false)
+        )
         .arg(
             Arg::with_name("output")
                 .short('o')
                 .long("output")
                 .value_name("output")
-                .help("The output folder, if not given, will be in the same folder as the input file")
+                .help("The output folder, if not given, will be in the same folder as the input file. With \"-\" the GFF is written to stdout and the other files to the current folder")
                 .takes_value(true)
                 .required(false)
                 .default_value(".")
         )
         .get_matches();
 
-    let refgff = parse_gff(
-        matches
-            .get_one::<String>("input")
-            .expect("Expect input file"),
-    )
-    .expect("Error parsing GFF file");
-
-    let gff_entries = refgff.entries;
-
-    // Get intergenic regions (start, end) in a vector
-    let min_distance = matches
-        .get_one::<String>("min_distance")
-        .unwrap()
-        .parse::<i64>()
-        .unwrap();
-
-    // TODO: Change it somehow to look at if the fasta file is given, if not, don't extract sequences
-    let mut refseqlen = 0;
-    let mut refseq = String::new();
-    match matches.get_one::<String>("fasta") {
-        Some(fasta) => {
-            let reffasta = parse_fasta(fasta).expect("Error parsing FASTA file");
-            refseq = reffasta[0].seq.to_string();
-            refseqlen = refseq.len() as i64;
-        }
-        None => {
-            println!("No FASTA file given, unable to determine length of sequence -> not extracting sequences");
-            println!("Exiting ...");
-            std::process::exit(0);
+    match run(&matches) {
+        Ok(0) => {}
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("\x1b[91mERROR:\x1b[0m {}", e);
+            std::process::exit(e.exit_code());
         }
     }
+}
 
-    // Add intergenic entries for each strand separately to the GFF entries
-    let mut merged_entries: Vec<GffEntry> = gff_entries.to_vec();
-    if matches.is_present("strandedness") {
-        for strand in ["+", "-"] {
-            let intergenic_region: Vec<(i64, i64)> =
-                get_intergenic_regions(&gff_entries, refseqlen, min_distance, strand);
-            let intergenic_entries =
-                create_intergenic_entries(intergenic_region, gff_entries[0].seqid.clone(), strand);
-            merged_entries.extend(intergenic_entries);
-        }
-    } else {
-        let intergenic_region: Vec<(i64, i64)> =
-            get_intergenic_regions(&gff_entries, refseqlen, min_distance, ".");
-        let intergenic_entries =
-            create_intergenic_entries(intergenic_region, gff_entries[0].seqid.clone(), ".");
-        merged_entries.extend(intergenic_entries);
-    }
+/// Returns the exit code, i.e 1 if the validation found any issues and 0 otherwise
+fn run(matches: &ArgMatches) -> Result<i32, IntergeneError> {
+    let input = matches
+        .get_one::<String>("input")
+        .expect("Expect input file");
+    let input_format = match matches
+        .get_one::<String>("input_format")
+        .map(|f| f.as_str())
+    {
+        Some("gtf") => GffFormat::Gtf,
+        Some(_) => GffFormat::Gff3,
+        None => GffFormat::from_path(input),
+    };
+    let mut refgff = parse_gff(input, input_format)?;
 
-    // Merge intergenic and gff entries and sort them
-    merged_entries.sort_by(|a, b| a.end.cmp(&b.end));
+    let gff_entries = &refgff.entries;
 
-    // Extract sequences from fasta file and add them to the entries
-    let merged_with_seq = add_seq_to_entries(&mut merged_entries, &refseq);
+    // Get intergenic regions (start, end) in a vector
+    let min_distance: i64 = parse_arg(matches, "min_distance")?.unwrap();
 
-    // Write new gff file, fasta files
-    write_gff_from_vec(&refgff.header, &merged_with_seq, "reference+intergenic.gff")
-        .expect("Unable to create the GFF file");
+    // Stranded regions get the strand in their ID by default, as + and - regions can be the same
+    let id_template = match matches.get_one::<String>("id_template") {
+        Some(template) => template.as_str(),
+        None if matches.is_present("strandedness") => "IGR_{seqid}_{start}_{end}_{strand}",
+        None => DEFAULT_ID_TEMPLATE,
+    };
 
-    let valid_types: Vec<String> = get_valid_types(&merged_with_seq);
+    // Trimming/extending the intergenic regions and filtering them by length
+    let trim: i64 = parse_arg(matches, "trim")?.unwrap();
+    let extend: i64 = parse_arg(matches, "extend")?.unwrap();
+    let min_length: i64 = parse_arg(matches, "min_length")?.unwrap();
+    let max_length: Option<i64> = parse_arg(matches, "max_length")?;
+    let flank_length: Option<i64> = parse_arg(matches, "flank_length")?;
+    let flank_type = match matches.get_one::<String>("flank_type").map(|t| t.as_str()) {
+        Some("utr") => FlankType::Utr,
+        _ => FlankType::Upstream,
+    };
 
-    // Only write fasta files for valid types
-    for entry_type in matches.get_many::<String>("types").expect("No types given") {
-        if valid_types.contains(entry_type) {
-            let output = matches
-                .get_one::<String>("output")
-                .expect("No output path given");
-            write_fasta_to_file(
-                entry_type,
-                &merged_with_seq,
-                &format!("{out}/{ttype}.fasta", out = output, ttype = entry_type),
-            )
-            .expect("Unable to write fasta file(s)");
-        } else {
-            println!(
-                "\x1b[91mERROR: Invalid entry type:\x1b[0m \x1b[94m{t}\x1b[0m. Not creating a fasta file for {t}. Please check if it was spelled correctly.",
-                t = entry_type
-            );
+    // Which types define the genic space, recorded in the header of the output
+    let genic_types = GenicTypes {
+        include: matches
+            .get_many::<String>("genic_types")
+            .map(|types| types.cloned().collect()),
+        exclude: matches
+            .get_many::<String>("ignore_types")
+            .map(|types| types.cloned().collect())
+            .unwrap_or_default(),
+    };
+    refgff
+        .header
+        .push_str(&format!("#intergene-finder: {}\n", genic_types));
+
+    // The sequences embedded in the GFF (after ##FASTA) are used if no FASTA file is given
+    // A FASTA file is indexed (.fai, and .gzi if bgzip compressed) so that only the bases of the
+    // entries are read from it, gzip compressed FASTA files (and stdin) can not be indexed and are
+    // read into memory instead
+    let mut embedded = std::mem::take(&mut refgff.sequences);
+    let reffasta = match matches.get_one::<String>("fasta") {
+        Some(fasta) if fasta == "-" || (is_compressed(fasta) && !is_bgzf(fasta)) => {
+            if fasta != "-" {
+                eprintln!(
+                    "{} is gzip but not bgzip compressed -> reading it into memory",
+                    fasta
+                );
+            }
+            embedded = parse_fasta(fasta)?;
+            None
         }
+        Some(fasta) => Some(IndexedFasta::open(fasta)?),
+        None => None,
+    };
+    let has_sequences = reffasta.is_some() || !embedded.is_empty();
+    if !has_sequences {
+        eprintln!("No FASTA file given -> not extracting sequences, only creating the GFF file");
     }
-}
+    let lengths = match matches.get_one::<String>("lengths") {
+        Some(lengths) => read_sequence_lengths(lengths)?,
+        None => Vec::new(),
+    };
+
+    // The sequence of a seqid, from the FASTA file or the GFF itself
+    let find_refseq = |seqid: &str| -> Option<Box<dyn SeqSource + '_>> {
+        match &reffasta {
+            Some(fasta) => fasta.get(seqid).map(|s| Box::new(s) as Box<dyn SeqSource>),
+            None => embedded
+                .iter()
+                .find(|s| s.id() == seqid)
+                .map(|s| Box::new(s) as Box<dyn SeqSource>),
+        }
+    };
+    // Length from the sequence itself, else the lengths file, else the GFF (##sequence-region/region)
+    let find_length = |seqid: &str, refseq: &Option<Box<dyn SeqSource + '_>>| -> Option<i64> {
+        refseq
+            .as_ref()
+            .map(|s| s.seq_len() as i64)
+            .or_else(|| lengths.iter().find(|(id, _)| id == seqid).map(|(_, l)| *l))
+            .or_else(|| refgff.sequence_length(seqid))
+    };
 
-/// Parses a GFF file and returns valid entry types i.e gene,CDS,transcript etc.
-fn get_valid_types(gff_entries: &Vec<GffEntry>) -> Vec<String> {
-    let mut valid_types: Vec<String> = Vec::new();
-    for entry in gff_entries {
-        if !valid_types.contains(&entry.r#type) {
-            valid_types.push(entry.r#type.clone());
+    // Compressed outputs get a .gz extension, stdout is compressed as well
+    let bgzip = matches.is_present("bgzip");
+    let gz = if bgzip { ".gz" } else { "" };
+
+    if matches.is_present("validate") {
+        let code: u8 = parse_arg(matches, "table")?.unwrap();
+        let code = GeneticCode::from_id(code).ok_or_else(|| IntergeneError::InvalidArgument {
+            name: "table".to_string(),
+            value: code.to_string(),
+        })?;
+        let mut findings: Vec<Finding> = Vec::new();
+        for (seqid, contig_entries) in group_by_seqid(gff_entries) {
+            let contig_entries: Vec<GffEntry> = contig_entries.into_iter().cloned().collect();
+            let refseq = find_refseq(seqid);
+            // With sequences given, a seqid is unknown if it has none, whatever its length
+            let length = match refseq {
+                None if has_sequences => None,
+                _ => find_length(seqid, &refseq),
+            };
+            let circular = matches.is_present("circular") || is_circular(&contig_entries);
+            findings.extend(validate_entries(
+                &contig_entries,
+                length,
+                refseq.as_deref(),
+                &code,
+                circular,
+            )?);
         }
+        let report = match matches.get_one::<String>("output").map(|o| o.as_str()) {
+            Some("-") => "-".to_string(),
+            Some(output) => format!("{}/validation.tsv{}", output, gz),
+            None => format!("validation.tsv{}", gz),
+        };
+        write_report(&findings, &report, bgzip)?;
+        eprintln!("Found {} issue(s), see {}", findings.len(), report);
+        return Ok(if findings.is_empty() { 0 } else { 1 });
     }
-    valid_types
-}
 
-/// For each intergenic region create a GFFEntry that has the same format as the other entries
-/// mainly start, end, type, and attributes i.e (ID,Name,locus_tag) defined
-fn create_intergenic_entries(
-    intergenic_regions: Vec<(i64, i64)>,
-    seqid: String,
-    strand: &str,
-) -> Vec<GffEntry> {
-    let intergenic_entries: Vec<GffEntry> = intergenic_regions
-        .iter()
-        .enumerate()
-        .map(|(idx, (start,end))| {
-            let seq = String::from("");
-            GffEntry {
-                // same seqid as the rest
-                seqid: seqid.clone(),
-                source: "intergene-finder".to_string(),
-                r#type: "intergenic".to_string(),
-                start: *start,
-                end: *end,
-                score: ".".to_string(),
-                strand: strand.to_string(),
-                phase: ".".to_string(),
-                attributes: format!(
-                    "ID=IGR_{a}({strand});Name=INTERGENIC_{a}({strand});locus_tag=INTERGENIC_{a}({strand})",
-                    a = idx + 1,
-                    strand = strand),
-                seq,
-            }
+    // Only the entries matching all attribute filters are extracted
+    let filters: Vec<AttributeFilter> = matches
+        .get_many::<String>("filter")
+        .map(|filters| {
+            filters
+                .map(|f| {
+                    AttributeFilter::parse(f).ok_or_else(|| IntergeneError::InvalidArgument {
+                        name: "filter".to_string(),
+                        value: f.to_string(),
+                    })
+                })
+                .collect::<Result<Vec<AttributeFilter>, IntergeneError>>()
         })
+        .transpose()?
+        .unwrap_or_default();
+    let types: Vec<&String> = matches
+        .get_many::<String>("types")
+        .expect("No types given")
         .collect();
-    intergenic_entries
-}
-
-/// Given a (vector of) GFF entry struct(s) return a the start and end of the intergenic regions
-/// as a vector of tuples
-fn get_intergenic_regions(
-    gff: &[GffEntry],
-    end: i64,
-    buffer: i64,
-    strand: &str,
-) -> Vec<(i64, i64)> {
-    // We obtain all the intergenic regions by going through a vector of GFFEntries
-    let mut regions: Vec<(i64, i64)> = Vec::new();
-
-    // should it start with 0 or 1? 0 means it includes (0,1) as intergenic range, if 1 is the first pos
-    // if 1 is the first
-    let mut last_end = match gff.first().unwrap().start {
-        1 => 1,
-        _ => 0,
+    // Only the entries written to the FASTA files get their sequence
+    let wanted = |entry: &GffEntry| {
+        types.contains(&&entry.r#type) && filters.iter().all(|f| f.matches(entry))
     };
+    let spliced = matches.get_one::<String>("spliced");
+
+    // Each seqid (chromosome, plasmid, contig) is handled on its own, so that IGRs never span
+    // contig boundaries and sequences are taken from the matching FASTA record
+    let mut merged: Vec<GffEntry> = Vec::new();
+    let mut extracted: Vec<GffEntry> = Vec::new();
+    let mut used_ids: HashMap<String, usize> = HashMap::new();
+    let mut seq_lengths: HashMap<String, i64> = HashMap::new();
+    for (seqid, contig_entries) in group_by_seqid(gff_entries) {
+        let refseq = find_refseq(seqid);
+        // Without its sequence the entries of a seqid would be written as empty FASTA records
+        if has_sequences && refseq.is_none() {
+            return Err(IntergeneError::UnknownSeqid(seqid.to_string()));
+        }
+        // The entries of the seqid, followed by the entries created for it below
+        let mut merged_entries: Vec<GffEntry> = contig_entries.into_iter().cloned().collect();
+        let given = merged_entries.len();
+        let refseqlen = match find_length(seqid, &refseq) {
+            Some(len) => len,
+            None => {
+                eprintln!(
+                    "\x1b[93mWARNING:\x1b[0m No sequence length found for seqid \x1b[94m{}\x1b[0m, not finding its intergenic regions",
+                    seqid
+                );
+                merged.extend(merged_entries);
+                continue;
+            }
+        };
 
-    // Filter if stranded and given, otherwise just go through all the entries i.e non-strand-specific IGRs
-    let filtered = match strand {
-        "+" => gff
-            .iter()
-            .filter(|x| x.strand == "+" || x.strand == ".")
-            .collect::<Vec<&GffEntry>>(),
-        "-" => gff
-            .iter()
-            .filter(|x| x.strand == "-" || x.strand == ".")
-            .collect::<Vec<&GffEntry>>(),
-        _ => gff.iter().collect::<Vec<&GffEntry>>(),
-    };
+        seq_lengths.insert(seqid.to_string(), refseqlen);
+        let circular = matches.is_present("circular") || is_circular(&merged_entries);
 
-    for entry in filtered {
-        // skip if type is any of:
-        if entry.r#type == "region" || entry.r#type == "sequence_feature" {
-            continue;
-        }
-        // If it happens right after stop codon, automatically update last end to be + 1
-        if entry.start == last_end + 1 {
-            last_end += 1;
+        // Add intergenic entries for each strand separately, they are kept apart from the GFF
+        // entries until their sequence (with the extension) was added
+        let mut intergenic: Vec<GffEntry> = Vec::new();
+        let strands: &[&str] = if matches.is_present("strandedness") {
+            &["+", "-"]
+        } else {
+            &["."]
+        };
+        for strand in strands {
+            let intergenic_region: Vec<IntergenicRegion> = get_intergenic_regions(
+                &merged_entries[..given],
+                refseqlen,
+                min_distance,
+                strand,
+                &genic_types,
+                circular,
+            );
+            // Flanks are taken from the untrimmed regions, i.e directly next to the genes
+            if let Some(flank_length) = flank_length {
+                merged_entries.extend(create_flanking_entries(
+                    &intergenic_region,
+                    seqid,
+                    flank_length,
+                    flank_type,
+                    refseqlen,
+                ));
+            }
+            let intergenic_region = filter_by_length(
+                trim_regions(intergenic_region, trim, refseqlen),
+                min_length,
+                max_length,
+            );
+            let mut intergenic_entries = create_intergenic_entries(
+                intergenic_region,
+                seqid.to_string(),
+                strand,
+                id_template,
+                &mut used_ids,
+            );
+            for entry in &mut intergenic_entries {
+                record_options(entry, trim, extend, min_length, max_length);
+            }
+            intergenic.extend(intergenic_entries);
         }
-        // if next entry is less than (i.e within) Xnt of previous end/entry, do not mark it as an intergenic
-        if entry.start < last_end + buffer {
-            continue;
+
+        // Introns between the exons of each transcript
+        if matches.is_present("introns") {
+            let introns = FeatureTree::new(&merged_entries[..given]).introns();
+            merged_entries.extend(introns);
         }
 
-        // if entry.start > last_end + buffer {
-        if entry.start > last_end {
-            regions.push((last_end + 1, entry.start - 1)); // NOTE: Remove if include end of previous seq
-                                                           // regions.push((last_end + 1, entry.start - 1));
+        // Extract the sequences of the wanted entries (and the children they are spliced from)
+        if let Some(refseq) = refseq {
+            let mut with_seq: Vec<GffEntry> = match spliced {
+                Some(child_type) => {
+                    let ids: Vec<&str> = merged_entries
+                        .iter()
+                        .filter(|e| wanted(e))
+                        .filter_map(|e| e.id())
+                        .collect();
+                    merged_entries
+                        .iter()
+                        .filter(|e| {
+                            wanted(e)
+                                || (e.r#type == *child_type
+                                    && e.parent_ids().iter().any(|parent| ids.contains(parent)))
+                        })
+                        .cloned()
+                        .collect()
+                }
+                None => merged_entries
+                    .iter()
+                    .filter(|e| wanted(e))
+                    .cloned()
+                    .collect(),
+            };
+            add_seq_to_entries(&mut with_seq, refseq.as_ref(), 0, circular)?;
+            if let Some(child_type) = spliced {
+                splice_entries(&mut with_seq, child_type);
+                with_seq.retain(|e| wanted(e));
+            }
+            let mut intergenic_with_seq: Vec<GffEntry> =
+                intergenic.iter().filter(|e| wanted(e)).cloned().collect();
+            add_seq_to_entries(&mut intergenic_with_seq, refseq.as_ref(), extend, circular)?;
+            with_seq.extend(intergenic_with_seq);
+            with_seq.sort_by_key(|e| e.end);
+            extracted.extend(with_seq);
         }
-        last_end = entry.end;
-    }
-    // Fill in the last if the end of the gff hasnt reached the end refernece sequence
-    if last_end < end {
-        regions.push((last_end, end)); //NOTE: Remove if include end of previous seq
-                                       // regions.push((last_end + 1, end));
-    }
-    regions
-}
 
-// Given a vector of GFF entries, add the sequence to each entry
-fn add_seq_to_entries(entries: &mut Vec<GffEntry>, refseq: &str) -> Vec<GffEntry> {
-    let mut entries_with_seq: Vec<GffEntry> = Vec::new();
-    for entry in entries {
-        let seq = refseq
-            .get(entry.start as usize - 1..entry.end as usize)
-            .unwrap()
-            .to_string();
-        entry.add_seq(seq);
-        entries_with_seq.push(entry.clone());
+        // Merge intergenic and gff entries and sort them
+        merged_entries.extend(intergenic);
+        merged_entries.sort_by_key(|e| e.end);
+        merged.extend(merged_entries);
     }
-    entries_with_seq
-}
 
-#[allow(dead_code)]
-fn parse_fasta(file: &str) -> Result<Vec<Seq>, FastaErrors> {
-    let file = match fs::read_to_string(file) {
-        Ok(file) => file,
-        Err(_) => return Err(FastaErrors::FileNotFound),
+    // Write new gff file, fasta files
+    let output_format = match matches
+        .get_one::<String>("output_format")
+        .map(|f| f.as_str())
+    {
+        Some("gtf") => GffFormat::Gtf,
+        Some(_) => GffFormat::Gff3,
+        None => input_format,
+    };
+    let output = matches
+        .get_one::<String>("output")
+        .expect("No output path given");
+    let (gff_output, output) = match output.as_str() {
+        "-" => ("-".to_string(), "."),
+        output => {
+            let extension = match output_format {
+                GffFormat::Gff3 => "gff",
+                GffFormat::Gtf => "gtf",
+            };
+            let gff_output = format!("{}/reference+intergenic.{}{}", output, extension, gz);
+            (gff_output, output)
+        }
     };
-    let mut entries: Vec<Seq> = Vec::new();
-    let mut header = String::new();
-    let mut seq = String::new();
-    for line in file.lines() {
-        let line = line.to_string();
-        if line.starts_with('>') {
-            if !header.is_empty() {
-                entries.push(Seq { header, seq });
+    match output_format {
+        GffFormat::Gff3 => write_gff_from_vec(&refgff.header, &merged, &gff_output, bgzip),
+        GffFormat::Gtf => write_gtf_from_vec(&refgff.header, &merged, &gff_output, bgzip),
+    }?;
+
+    let valid_types: Vec<String> = get_valid_types(&merged);
+
+    let genetic_code = if matches.is_present("translate") {
+        let table: u8 = parse_arg(matches, "table")?.unwrap();
+        Some(
+            GeneticCode::from_id(table).ok_or_else(|| IntergeneError::InvalidArgument {
+                name: "table".to_string(),
+                value: table.to_string(),
+            })?,
+        )
+    } else {
+        None
+    };
+    let header_template = matches
+        .get_one::<String>("header_template")
+        .map(|t| t.as_str())
+        .unwrap_or(DEFAULT_HEADER_TEMPLATE);
+
+    // Only write fasta files for valid types, and only if there are sequences to write
+    let bed_format = if matches.is_present("bed12") {
+        Some(BedFormat::Bed12)
+    } else if matches.is_present("bed") {
+        Some(BedFormat::Bed6)
+    } else {
+        None
+    };
+    for entry_type in types {
+        if valid_types.contains(entry_type) {
+            if let Some(bed_format) = bed_format {
+                // The filters only apply to the records written, not to their exons/CDS
+                let bed_entries: Vec<GffEntry> = merged
+                    .iter()
+                    .filter(|e| e.r#type != *entry_type || filters.iter().all(|f| f.matches(e)))
+                    .cloned()
+                    .collect();
+                write_bed_from_vec(
+                    entry_type,
+                    &bed_entries,
+                    &seq_lengths,
+                    &format!("{out}/{ttype}.bed{gz}", out = output, ttype = entry_type),
+                    bed_format,
+                    bgzip,
+                )?;
+            }
+            if has_sequences {
+                write_fasta_to_file(
+                    entry_type,
+                    &extracted,
+                    &format!("{out}/{ttype}.fasta{gz}", out = output, ttype = entry_type),
+                    header_template,
+                    bgzip,
+                )?;
+                if let Some(code) = &genetic_code {
+                    write_protein_fasta(
+                        entry_type,
+                        &extracted,
+                        &format!("{out}/{ttype}.faa{gz}", out = output, ttype = entry_type),
+                        header_template,
+                        code,
+                        bgzip,
+                    )?;
+                }
             }
-            header = line;
-            seq = String::new();
         } else {
-            seq.push_str(&line);
+            eprintln!(
+                "\x1b[91mERROR: Invalid entry type:\x1b[0m \x1b[94m{t}\x1b[0m. Not creating a fasta file for {t}. Please check if it was spelled correctly.",
+                t = entry_type
+            );
         }
     }
-    entries.push(Seq { header, seq });
-    Ok(entries)
+    Ok(0)
 }
 
-fn parse_gff(file: &str) -> Result<Gff, GFFErrors> {
-    // Take a filename and parse it into a GFF struct
-
-    let file = match fs::read_to_string(file) {
-        Ok(file) => file,
-        Err(_) => return Err(GFFErrors::FileNotFound),
-    };
+/// Parses the value of an argument, None if it was not given
+fn parse_arg<T: std::str::FromStr>(
+    matches: &ArgMatches,
+    name: &str,
+) -> Result<Option<T>, IntergeneError> {
+    matches
+        .get_one::<String>(name)
+        .map(|value| {
+            value
+                .parse::<T>()
+                .map_err(|_| IntergeneError::InvalidArgument {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+        })
+        .transpose()
+}
 
-    // Header info
-    let mut header = String::new();
-    for line in file.lines() {
-        let line = line.to_string();
-        if line.starts_with('#') {
-            let _ = writeln!(header, "{}", &line);
-            // header.push_str(&format!("{}\n", &line));
-        } else {
-            break;
-        }
+/// Records the trimming/extension and length filters that were used as attributes of an
+/// intergenic entry, leaving out the ones that were not used
+fn record_options(
+    entry: &mut GffEntry,
+    trim: i64,
+    extend: i64,
+    min_length: i64,
+    max_length: Option<i64>,
+) {
+    if trim > 0 {
+        entry.attributes.insert("trim", vec![trim.to_string()]);
     }
-
-    // Entry info
-    let mut entries: Vec<GffEntry> = Vec::new();
-    for line in file.lines() {
-        let line = line.to_string();
-        // Skip comments & header lines
-        if line.starts_with('#') {
-            continue;
-        }
-
-        let parts: Vec<&str> = line.split('\t').collect();
-
-        // Check if the line has the correct number of columns, create entry if so
-        // error if not
-        let gffrecord = match parts.len() {
-            9 => GffEntry {
-                seqid: parts[0].to_string(),
-                source: parts[1].to_string(),
-                r#type: parts[2].to_string(),
-                start: parts[3].parse::<i64>().unwrap(),
-                end: parts[4].parse::<i64>().unwrap(),
-                score: parts[5].to_string(),
-                strand: parts[6].to_string(),
-                phase: parts[7].to_string(),
-                attributes: parts[8].to_string(),
-                seq: String::new(),
-            },
-            _ => return Err(GFFErrors::GFFColumnMismatch),
-        };
-        entries.push(gffrecord);
+    if extend > 0 {
+        entry.attributes.insert("extend", vec![extend.to_string()]);
     }
-    Ok(Gff { header, entries })
-}
-/// More generic fasta writer
-fn write_fasta_to_file(
-    entry_type: &str,
-    gff_entries: &Vec<GffEntry>,
-    filename: &str,
-) -> Result<(), Error> {
-    let mut to_write = String::new();
-    // For entries matching type, write their sequences to a file
-    for entry in gff_entries {
-        if entry.r#type == entry_type {
-            let _ = writeln!(
-                to_write,
-                ">{} length: {}",
-                entry.attributes,
-                entry.seq.len()
-            );
-            let mut seq = entry.seq.clone();
-            while seq.len() > 80 {
-                let _ = writeln!(to_write, "{}", &seq[..80]);
-                // to_write.push_str(&format!("{}\n", &seq[..80]));
-                seq = seq[80..].to_string();
-            }
-            // to_write.push_str(&format!("{}\n", &seq));
-            let _ = writeln!(to_write, "{}", &seq);
-        }
-        // to_write.push_str(&format!(">{}\n{}\n", entry.attributes, entry.seq));
+    if min_length > 1 {
+        entry
+            .attributes
+            .insert("min_length", vec![min_length.to_string()]);
+    }
+    if let Some(max_length) = max_length {
+        entry
+            .attributes
+            .insert("max_length", vec![max_length.to_string()]);
     }
-    fs::write(filename, to_write)?;
-    Ok(())
 }
 
-// Create a new GFF file that includes the (intergenic) regions that we added
-fn write_gff_from_vec(
-    header: &String,
-    gff_entries: &Vec<GffEntry>,
-    fname: &str,
-) -> Result<(), Error> {
-    // Recreate a gff file from the header, entries to include and write it to a file
-    let mut gff_file = String::new();
-    let _ = writeln!(gff_file, "{}\n", header);
-    // gff_file.push_str(&format!("{}\n", header));
-    for entry in gff_entries {
-        let _ = writeln!(
-            gff_file,
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
-            entry.seqid,
-            entry.source,
-            entry.r#type,
-            entry.start,
-            entry.end,
-            entry.score,
-            entry.strand,
-            entry.phase,
-            entry.attributes,
-        );
+/// Replaces the sequence of every entry that has children of the given type (e.g exon or CDS)
+/// by its spliced sequence, see `FeatureTree::spliced_seq`
+fn splice_entries(entries: &mut [GffEntry], child_type: &str) {
+    let spliced: Vec<Option<String>> = {
+        let tree = FeatureTree::new(entries);
+        entries
+            .iter()
+            .map(|entry| tree.spliced_seq(entry, child_type))
+            .collect()
+    };
+    for (entry, seq) in entries.iter_mut().zip(spliced) {
+        if let Some(seq) = seq {
+            entry.add_seq(seq);
+        }
     }
-    fs::write(fname, gff_file)?;
-    Ok(())
 }
@@ -0,0 +1,361 @@
+//! Transparent reading of gzip/bgzip compressed files and writing of bgzip (BGZF) files
+//!
+//! Compressed inputs are recognised by their magic bytes, not their extension. Outputs are
+//! bgzip compressed if asked for or if their name ends in `.gz`, so they can be read by `gzip`,
+//! `samtools` and `tabix` alike. A file name of `-` stands for stdin/stdout.
+//! BGZF files can be read from any position with a `.gzi` index (see `GziIndex`).
+
+use crate::error::IntergeneError;
+use flate2::bufread::MultiGzDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+
+/// The first two bytes of every gzip (and so bgzip) file
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The most uncompressed bytes put into a single BGZF block (as bgzip does), so that the
+/// compressed block always fits the 64 KiB limit
+const BGZF_BLOCK_SIZE: usize = 0xff00;
+
+/// The empty block marking the end of a BGZF file
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Whether the file starts with the gzip magic bytes
+pub fn is_compressed(file: &str) -> bool {
+    let mut reader = match File::open(file) {
+        Ok(file) => BufReader::new(file),
+        Err(_) => return false,
+    };
+    matches!(reader.fill_buf(), Ok(bytes) if bytes.starts_with(&GZIP_MAGIC))
+}
+
+/// Whether the file is BGZF, i.e gzip with the BC extra field (holding the block size)
+pub fn is_bgzf(file: &str) -> bool {
+    let mut header = [0; 14];
+    match File::open(file).and_then(|mut reader| reader.read_exact(&mut header)) {
+        Ok(_) => header.starts_with(&GZIP_MAGIC) && header[3] & 0x04 != 0 && &header[12..] == b"BC",
+        Err(_) => false,
+    }
+}
+
+/// A (`bgzip -i` compatible) .gzi index of a BGZF file, i.e the compressed and uncompressed
+/// offsets at which its blocks start
+/// The file stores the number of blocks followed by the offset pairs, leaving out the first block
+/// (which starts at 0, 0), as little endian 64 bit integers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GziIndex {
+    blocks: Vec<(u64, u64)>,
+}
+
+impl GziIndex {
+    /// Reads a .gzi index
+    pub fn read(file: &str) -> Result<GziIndex, IntergeneError> {
+        let bytes = std::fs::read(file).map_err(|e| IntergeneError::io(file, e))?;
+        let numbers: Vec<u64> = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        match numbers.split_first() {
+            Some((count, pairs))
+                if bytes.len() % 8 == 0 && pairs.len() as u64 == count.saturating_mul(2) =>
+            {
+                let mut blocks = vec![(0, 0)];
+                blocks.extend(pairs.chunks_exact(2).map(|pair| (pair[0], pair[1])));
+                Ok(GziIndex { blocks })
+            }
+            _ => Err(IntergeneError::io(
+                file,
+                io::Error::new(ErrorKind::InvalidData, "not a .gzi index"),
+            )),
+        }
+    }
+
+    /// Creates the index of a BGZF file from the headers and sizes of its blocks, without
+    /// decompressing them
+    pub fn build(file: &str) -> Result<GziIndex, IntergeneError> {
+        let mut reader = File::open(file).map_err(|e| IntergeneError::io(file, e))?;
+        let length = reader
+            .metadata()
+            .map_err(|e| IntergeneError::io(file, e))?
+            .len();
+        let mut blocks = vec![(0, 0)];
+        let (mut compressed, mut uncompressed) = (0, 0);
+        while compressed < length {
+            let mut header = [0; 18];
+            let mut isize = [0; 4];
+            reader
+                .seek(SeekFrom::Start(compressed))
+                .and_then(|_| reader.read_exact(&mut header))
+                .map_err(|e| IntergeneError::io(file, e))?;
+            if !header.starts_with(&GZIP_MAGIC) || &header[12..14] != b"BC" {
+                return Err(IntergeneError::io(
+                    file,
+                    io::Error::new(ErrorKind::InvalidData, "not a BGZF (bgzip) file"),
+                ));
+            }
+            // BSIZE is the total block size - 1, ISIZE (the uncompressed size) its last 4 bytes
+            let block_size = u16::from_le_bytes([header[16], header[17]]) as u64 + 1;
+            reader
+                .seek(SeekFrom::Start(compressed + block_size - 4))
+                .and_then(|_| reader.read_exact(&mut isize))
+                .map_err(|e| IntergeneError::io(file, e))?;
+            if compressed > 0 {
+                blocks.push((compressed, uncompressed));
+            }
+            compressed += block_size;
+            uncompressed += u32::from_le_bytes(isize) as u64;
+        }
+        Ok(GziIndex { blocks })
+    }
+
+    /// Writes the index to a .gzi file
+    pub fn write(&self, file: &str) -> Result<(), IntergeneError> {
+        let mut bytes = ((self.blocks.len() - 1) as u64).to_le_bytes().to_vec();
+        for (compressed, uncompressed) in &self.blocks[1..] {
+            bytes.extend_from_slice(&compressed.to_le_bytes());
+            bytes.extend_from_slice(&uncompressed.to_le_bytes());
+        }
+        std::fs::write(file, bytes).map_err(|e| IntergeneError::io(file, e))
+    }
+
+    /// Fills `buf` with the decompressed bytes of the indexed file starting at `offset`, only
+    /// decompressing the blocks from the one holding `offset` on
+    pub fn read_at(&self, file: &File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let block = self.blocks.partition_point(|(_, start)| *start <= offset) - 1;
+        let (compressed, uncompressed) = self.blocks[block];
+        let mut file = file;
+        file.seek(SeekFrom::Start(compressed))?;
+        let mut decoder = MultiGzDecoder::new(BufReader::new(file));
+        io::copy(
+            &mut (&mut decoder).take(offset - uncompressed),
+            &mut io::sink(),
+        )?;
+        decoder.read_exact(buf)
+    }
+}
+
+/// Opens a plain, gzip or bgzip compressed file (or stdin) for reading
+pub fn open(file: &str) -> Result<Box<dyn BufRead>, IntergeneError> {
+    let reader: Box<dyn Read> = match file {
+        "-" => Box::new(io::stdin()),
+        path => Box::new(File::open(path).map_err(|e| IntergeneError::io(file, e))?),
+    };
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf().map_err(|e| IntergeneError::io(file, e))?;
+    if magic.starts_with(&GZIP_MAGIC) {
+        // bgzip files consist of many gzip members
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// A buffered writer to a file or stdout, see `create`
+/// Call `finish` when done, which writes the end of bgzip compressed output and reports whether
+/// that failed (dropping it writes the end as well, but ignores errors)
+pub enum Output {
+    Plain(io::BufWriter<Box<dyn Write>>),
+    Bgzf(BgzfWriter<Box<dyn Write>>),
+}
+
+impl Output {
+    fn new(inner: Box<dyn Write>, bgzip: bool) -> Output {
+        if bgzip {
+            Output::Bgzf(BgzfWriter::new(inner))
+        } else {
+            Output::Plain(io::BufWriter::new(inner))
+        }
+    }
+
+    /// Writes the remaining data (and the end-of-file block of bgzip output)
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut writer) => writer.flush(),
+            Output::Bgzf(writer) => writer.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Bgzf(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Bgzf(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Creates a file (or stdout for `-`) for writing, which is bgzip compressed if `bgzip` is set or
+/// its name ends in `.gz`. This is how all output files are written, also by `write_file`
+pub fn create(file: &str, bgzip: bool) -> Result<Output, IntergeneError> {
+    if file == "-" {
+        return Ok(stdout(bgzip));
+    }
+    let writer = File::create(file).map_err(|e| IntergeneError::io(file, e))?;
+    Ok(Output::new(
+        Box::new(writer),
+        bgzip || file.ends_with(".gz"),
+    ))
+}
+
+/// A buffered writer to stdout, bgzip compressed if `bgzip` is set
+pub fn stdout(bgzip: bool) -> Output {
+    Output::new(Box::new(io::stdout()), bgzip)
+}
+
+/// Writes the contents to a file, compressed as described in `create`
+pub fn write_file(file: &str, contents: &str, bgzip: bool) -> Result<(), IntergeneError> {
+    let mut writer = create(file, bgzip)?;
+    writer
+        .write_all(contents.as_bytes())
+        .and_then(|_| writer.finish())
+        .map_err(|e| IntergeneError::io(file, e))
+}
+
+/// Writes BGZF, i.e gzip made of independently compressed blocks of at most 64 KiB
+/// The end-of-file block is written by `finish` or, ignoring errors, when it is dropped
+pub struct BgzfWriter<W: Write> {
+    inner: Option<W>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W) -> BgzfWriter<W> {
+        BgzfWriter {
+            inner: Some(inner),
+            buffer: Vec::with_capacity(BGZF_BLOCK_SIZE),
+        }
+    }
+
+    /// Writes the remaining data and the end-of-file block, returning the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_eof()?;
+        Ok(self.inner.take().expect("BGZF writer already finished"))
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.buffer)?;
+        let compressed = encoder.finish()?;
+        let mut crc = Crc::new();
+        crc.update(&self.buffer);
+
+        // gzip header with the BC extra field holding the total block size - 1
+        let block_size = (compressed.len() + 25) as u16;
+        let mut block: Vec<u8> = vec![
+            0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43,
+            0x02, 0x00,
+        ];
+        block.extend_from_slice(&block_size.to_le_bytes());
+        block.extend_from_slice(&compressed);
+        block.extend_from_slice(&crc.sum().to_le_bytes());
+        block.extend_from_slice(&(self.buffer.len() as u32).to_le_bytes());
+        if let Some(inner) = self.inner.as_mut() {
+            inner.write_all(&block)?;
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn write_eof(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        if let Some(inner) = self.inner.as_mut() {
+            inner.write_all(&BGZF_EOF)?;
+            inner.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let taken = buf.len().min(BGZF_BLOCK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..taken]);
+        if self.buffer.len() == BGZF_BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        match self.inner.as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for BgzfWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.write_eof();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bgzf_round_trips_through_gzip() {
+        // More than one block
+        let contents: String = (0..20000).map(|i| format!("chr1\t{}\t7\n", i)).collect();
+        let mut writer = BgzfWriter::new(Vec::new());
+        writer.write_all(contents.as_bytes()).unwrap();
+        let compressed = writer.finish().unwrap();
+        assert!(compressed.starts_with(&GZIP_MAGIC));
+        assert!(compressed.ends_with(&BGZF_EOF));
+
+        let mut decompressed = String::new();
+        MultiGzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, contents);
+    }
+
+    /// Accepts `capacity` bytes, failing on any write after that
+    struct Full {
+        capacity: usize,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > self.capacity {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "no space left"));
+            }
+            self.capacity -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn finishing_reports_failed_final_writes() {
+        // The bgzip block of the data fits, the end-of-file block does not
+        for (bgzip, capacity) in [(false, 5), (true, 40)] {
+            let mut output = Output::new(Box::new(Full { capacity }), bgzip);
+            output.write_all(b"chr1\t1\t7\n").unwrap();
+            assert!(output.finish().is_err());
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::bed::BedRegion;
+use crate::compression;
+use crate::error::IntergeneError;
+use std::io::prelude::*;
+
+#[derive(Debug)]
+pub enum DepthErrors {
+    /// The line has less than 3 columns
+    DepthColumnMismatch(String),
+    /// The position (column 2) or depth (column 3) of the line is not an integer
+    InvalidValue(String, usize),
+}
+
+impl DepthErrors {
+    /// Turns the error into an `IntergeneError::Parse` naming the file and line it occurred in
+    pub fn in_file(self, path: &str, line: usize) -> IntergeneError {
+        match self {
+            DepthErrors::DepthColumnMismatch(_) => {
+                IntergeneError::parse(path, line, None, "expected at least 3 columns")
+            }
+            DepthErrors::InvalidValue(_, column) => {
+                IntergeneError::parse(path, line, Some(column), "the value is not an integer")
+            }
+        }
+    }
+}
+
+/// A single line of the output of "samtools depth", along with the name of the region it belongs to
+#[derive(Debug)]
+pub struct DepthInfo {
+    pub chromosome: String,
+    pub basenumber: i64,
+    pub reads: i64,
+    pub name: String,
+}
+
+impl DepthInfo {
+    /// Create a depth record from the (tab separated) columns of a single depth line
+    pub fn from_fields(fields: &[&str]) -> Result<DepthInfo, DepthErrors> {
+        let line = fields.join("\t");
+        if fields.len() < 3 {
+            return Err(DepthErrors::DepthColumnMismatch(line));
+        }
+        let basenumber = fields[1]
+            .parse::<i64>()
+            .map_err(|_| DepthErrors::InvalidValue(line.clone(), 2))?;
+        let reads = fields[2]
+            .parse::<i64>()
+            .map_err(|_| DepthErrors::InvalidValue(line.clone(), 3))?;
+        Ok(DepthInfo {
+            chromosome: fields[0].to_string(),
+            basenumber,
+            reads,
+            name: "".to_string(),
+        })
+    }
+
+    pub fn add_name(&mut self, name: &str) {
+        self.name.push_str(name);
+    }
+}
+
+/// Adds the name of the region to the depth file, based on the bed file
+/// input: vector of depth info, vector of bed regions
+/// output: mutated original vector of depth info with the name of the region added
+/// Without any bed regions the names are left empty
+pub fn add_name_to_depth(depths: &mut [DepthInfo], bed_regions: &[BedRegion]) {
+    if bed_regions.is_empty() {
+        return;
+    }
+    let mut idx = 0;
+    for depth in depths {
+        if depth.basenumber <= bed_regions[idx].end {
+            depth.name = bed_regions[idx].name.clone();
+        } else {
+            if idx < bed_regions.len() - 1 {
+                idx += 1;
+            }
+            depth.name = bed_regions[idx].name.clone();
+        }
+    }
+}
+
+/// Read a (possibly gzip/bgzip compressed) .depth file from the output of the samtools depth command
+pub fn read_depths(filename: &str) -> Result<Vec<DepthInfo>, IntergeneError> {
+    let reader = compression::open(filename)?;
+    let mut depths: Vec<DepthInfo> = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| IntergeneError::io(filename, e))?;
+        let split_line = line.split('\t').collect::<Vec<&str>>();
+        depths.push(DepthInfo::from_fields(&split_line).map_err(|e| e.in_file(filename, idx + 1))?);
+    }
+    Ok(depths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_left_empty_without_regions() {
+        let mut depths = vec![DepthInfo::from_fields(&["chr1", "1", "7"]).unwrap()];
+        add_name_to_depth(&mut depths, &[]);
+        assert_eq!(depths[0].name, "");
+
+        let region = BedRegion::from_fields(&["chr1", "0", "10", "IGR_1"]).unwrap();
+        add_name_to_depth(&mut depths, &[region]);
+        assert_eq!(depths[0].name, "IGR_1");
+    }
+}
@@ -0,0 +1,121 @@
+//! The error type shared by the parsers, the sequence extraction and the binaries
+//!
+//! The per-format errors (`GFFErrors`, `BedErrors`, `DepthErrors`) describe what is wrong with a
+//! single line, the file-level functions turn them into an `IntergeneError` naming the file, line
+//! and column.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum IntergeneError {
+    /// A file (`-` for stdin/stdout) could not be opened, read or written
+    Io { path: String, source: io::Error },
+    /// A line of an input file could not be parsed, line and column are 1-based
+    Parse {
+        path: String,
+        line: usize,
+        column: Option<usize>,
+        message: String,
+    },
+    /// An entry does not satisfy 1 <= start <= end
+    InvalidCoordinates { seqid: String, start: i64, end: i64 },
+    /// An attribute has a value that can not be used, e.g an unknown `transl_table`
+    InvalidAttribute {
+        entry: String,
+        key: String,
+        value: String,
+    },
+    /// A sequence that is not in the FASTA file (or sequence lengths)
+    UnknownSeqid(String),
+    /// Bases outside of the sequence were asked for
+    OutOfBounds {
+        seqid: String,
+        start: i64,
+        end: i64,
+        length: usize,
+    },
+    /// A command line argument has a value that can not be used
+    InvalidArgument { name: String, value: String },
+}
+
+impl IntergeneError {
+    pub fn io(path: &str, source: io::Error) -> IntergeneError {
+        IntergeneError::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub fn parse(path: &str, line: usize, column: Option<usize>, message: &str) -> IntergeneError {
+        IntergeneError::Parse {
+            path: path.to_string(),
+            line,
+            column,
+            message: message.to_string(),
+        }
+    }
+
+    /// The exit code the binaries use for this kind of error
+    /// Invalid arguments exit with 2, like the usage errors clap reports itself
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            IntergeneError::InvalidArgument { .. } => 2,
+            IntergeneError::Io { .. } => 3,
+            IntergeneError::Parse { .. } => 4,
+            IntergeneError::InvalidCoordinates { .. } => 5,
+            IntergeneError::InvalidAttribute { .. } => 6,
+            IntergeneError::UnknownSeqid(_) => 7,
+            IntergeneError::OutOfBounds { .. } => 8,
+        }
+    }
+}
+
+impl fmt::Display for IntergeneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntergeneError::Io { path, source } => write!(f, "{}: {}", path, source),
+            IntergeneError::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => match column {
+                Some(column) => write!(f, "{}:{}:{}: {}", path, line, column, message),
+                None => write!(f, "{}:{}: {}", path, line, message),
+            },
+            IntergeneError::InvalidCoordinates { seqid, start, end } => write!(
+                f,
+                "invalid coordinates {}:{}-{}, they have to be 1 <= start <= end",
+                seqid, start, end
+            ),
+            IntergeneError::InvalidAttribute { entry, key, value } => {
+                write!(f, "invalid {} \"{}\" of {}", key, value, entry)
+            }
+            IntergeneError::UnknownSeqid(seqid) => write!(f, "unknown sequence {}", seqid),
+            IntergeneError::OutOfBounds {
+                seqid,
+                start,
+                end,
+                length,
+            } => write!(
+                f,
+                "{}:{}-{} is out of bounds, {} is {} bases long",
+                seqid, start, end, seqid, length
+            ),
+            IntergeneError::InvalidArgument { name, value } => {
+                write!(f, "invalid value \"{}\" for --{}", value, name)
+            }
+        }
+    }
+}
+
+impl Error for IntergeneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            IntergeneError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,647 @@
+use crate::compression::{self, GziIndex};
+use crate::error::IntergeneError;
+use crate::gff::GffEntry;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom};
+
+/// A single FASTA record, the header includes the leading '>'
+pub struct Seq {
+    pub header: String,
+    pub seq: String,
+}
+
+impl Seq {
+    /// The sequence ID, i.e the first word of the header without the leading '>'
+    pub fn id(&self) -> &str {
+        self.header
+            .trim_start_matches('>')
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+    }
+}
+
+/// Reads all records of a (possibly gzip/bgzip compressed) FASTA file into memory
+pub fn parse_fasta(file: &str) -> Result<Vec<Seq>, IntergeneError> {
+    read_fasta_lines(compression::open(file)?.lines()).map_err(|e| IntergeneError::io(file, e))
+}
+
+/// Collects FASTA records from an iterator of lines, e.g the lines after a `##FASTA` directive
+pub fn read_fasta_lines<I>(lines: I) -> io::Result<Vec<Seq>>
+where
+    I: Iterator<Item = io::Result<String>>,
+{
+    let mut entries: Vec<Seq> = Vec::new();
+    let mut header = String::new();
+    let mut seq = String::new();
+    for line in lines {
+        let line = line?;
+        if line.starts_with('>') {
+            if !header.is_empty() {
+                entries.push(Seq { header, seq });
+            }
+            header = line;
+            seq = String::new();
+        } else {
+            seq.push_str(line.trim_end());
+        }
+    }
+    if !header.is_empty() {
+        entries.push(Seq { header, seq });
+    }
+    Ok(entries)
+}
+
+/// Reads the sequence lengths from a .fai index or a chrom.sizes file, both of which have the
+/// sequence name in the first and its length in the second (tab separated) column
+pub fn read_sequence_lengths(file: &str) -> Result<Vec<(String, i64)>, IntergeneError> {
+    let reader = compression::open(file)?;
+    let mut lengths: Vec<(String, i64)> = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| IntergeneError::io(file, e))?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut columns = line.split('\t');
+        let name = columns.next().unwrap_or("").to_string();
+        let length = columns
+            .next()
+            .and_then(|l| l.trim().parse::<i64>().ok())
+            .ok_or_else(|| {
+                IntergeneError::parse(file, idx + 1, Some(2), "the length is not an integer")
+            })?;
+        lengths.push((name, length));
+    }
+    Ok(lengths)
+}
+
+/// Something bases can be taken from, i.e a sequence held in memory or one read from an indexed
+/// FASTA file on demand
+pub trait SeqSource {
+    /// The ID of the sequence
+    fn seq_id(&self) -> &str;
+    /// The number of bases in the sequence
+    fn seq_len(&self) -> usize;
+    /// The bases from `start` to `end` (0-based, end exclusive)
+    fn fetch(&self, start: usize, end: usize) -> Result<String, IntergeneError>;
+
+    /// The error for bases from `start` to `end` (0-based, end exclusive) outside the sequence
+    fn out_of_bounds(&self, start: usize, end: usize) -> IntergeneError {
+        IntergeneError::OutOfBounds {
+            seqid: self.seq_id().to_string(),
+            start: start as i64 + 1,
+            end: end as i64,
+            length: self.seq_len(),
+        }
+    }
+}
+
+impl SeqSource for Seq {
+    fn seq_id(&self) -> &str {
+        self.id()
+    }
+
+    fn seq_len(&self) -> usize {
+        self.seq.len()
+    }
+
+    fn fetch(&self, start: usize, end: usize) -> Result<String, IntergeneError> {
+        self.seq
+            .get(start..end)
+            .map(|s| s.to_string())
+            .ok_or_else(|| self.out_of_bounds(start, end))
+    }
+}
+
+impl<T: SeqSource + ?Sized> SeqSource for &T {
+    fn seq_id(&self) -> &str {
+        (**self).seq_id()
+    }
+
+    fn seq_len(&self) -> usize {
+        (**self).seq_len()
+    }
+
+    fn fetch(&self, start: usize, end: usize) -> Result<String, IntergeneError> {
+        (**self).fetch(start, end)
+    }
+}
+
+/// A record of a (samtools faidx compatible) .fai index
+/// `offset` is the byte offset of the first base, every line but the last of a record holds
+/// `line_bases` bases and is `line_width` bytes long (including the line break)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaiRecord {
+    pub name: String,
+    pub length: u64,
+    pub offset: u64,
+    pub line_bases: u64,
+    pub line_width: u64,
+}
+
+/// Reads a .fai index
+pub fn read_fai(file: &str) -> Result<Vec<FaiRecord>, IntergeneError> {
+    let reader = File::open(file).map_err(|e| IntergeneError::io(file, e))?;
+    let mut records: Vec<FaiRecord> = Vec::new();
+    for (idx, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.map_err(|e| IntergeneError::io(file, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let columns: Vec<&str> = line.split('\t').collect();
+        let number = |column: usize| {
+            columns
+                .get(column)
+                .and_then(|c| c.trim().parse::<u64>().ok())
+                .ok_or_else(|| {
+                    IntergeneError::parse(file, idx + 1, Some(column + 1), "not an integer")
+                })
+        };
+        records.push(FaiRecord {
+            name: columns[0].to_string(),
+            length: number(1)?,
+            offset: number(2)?,
+            line_bases: number(3)?,
+            line_width: number(4)?,
+        });
+    }
+    Ok(records)
+}
+
+/// Creates the .fai index of a FASTA file by scanning it once, without keeping the sequences
+/// The offsets of compressed files are those in the decompressed file, as for samtools
+pub fn build_fai(file: &str) -> Result<Vec<FaiRecord>, IntergeneError> {
+    let mut reader = compression::open(file)?;
+    let mut records: Vec<FaiRecord> = Vec::new();
+    let mut line: Vec<u8> = Vec::new();
+    let mut offset: u64 = 0;
+    let mut line_number = 0;
+    // Set once a line shorter than the first line of a record was read, i.e the last line
+    let mut short_line = false;
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| IntergeneError::io(file, e))? as u64;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+        offset += read;
+        let bases = line
+            .iter()
+            .take_while(|b| **b != b'\n' && **b != b'\r')
+            .count() as u64;
+        if line.starts_with(b">") {
+            let header = String::from_utf8_lossy(&line[1..]);
+            records.push(FaiRecord {
+                name: header.split_whitespace().next().unwrap_or("").to_string(),
+                length: 0,
+                offset,
+                line_bases: 0,
+                line_width: 0,
+            });
+            short_line = false;
+            continue;
+        }
+        let record = match records.last_mut() {
+            Some(record) if bases > 0 => record,
+            _ => continue,
+        };
+        if record.line_bases == 0 {
+            record.line_bases = bases;
+            record.line_width = read;
+        } else if short_line || bases > record.line_bases {
+            return Err(IntergeneError::parse(
+                file,
+                line_number,
+                None,
+                "all lines of a record but the last have to be equally long to index the file",
+            ));
+        } else if bases < record.line_bases || read != record.line_width {
+            short_line = true;
+        }
+        record.length += bases;
+    }
+    Ok(records)
+}
+
+/// Writes a .fai index
+pub fn write_fai(records: &[FaiRecord], filename: &str) -> Result<(), IntergeneError> {
+    let mut to_write = String::new();
+    for record in records {
+        let _ = writeln!(
+            to_write,
+            "{}\t{}\t{}\t{}\t{}",
+            record.name, record.length, record.offset, record.line_bases, record.line_width
+        );
+    }
+    fs::write(filename, to_write).map_err(|e| IntergeneError::io(filename, e))?;
+    Ok(())
+}
+
+/// A FASTA file with a .fai index, only the bases that are asked for are read from it
+/// bgzip compressed files additionally need a .gzi index, see `compression::GziIndex`
+pub struct IndexedFasta {
+    path: String,
+    file: File,
+    gzi: Option<GziIndex>,
+    pub records: Vec<FaiRecord>,
+}
+
+impl IndexedFasta {
+    /// Opens a (possibly bgzip compressed) FASTA file along with its index (`<file>.fai`, and
+    /// `<file>.gzi` if compressed)
+    /// The indices are created if there are none, they are only kept in memory if they can not be
+    /// written
+    pub fn open(file: &str) -> Result<IndexedFasta, IntergeneError> {
+        let fai = format!("{}.fai", file);
+        let records = match read_fai(&fai) {
+            Ok(records) => records,
+            Err(IntergeneError::Io { source, .. }) if source.kind() == ErrorKind::NotFound => {
+                let records = build_fai(file)?;
+                let _ = write_fai(&records, &fai);
+                records
+            }
+            Err(e) => return Err(e),
+        };
+        let gzi = if compression::is_bgzf(file) {
+            let gzi = format!("{}.gzi", file);
+            Some(match GziIndex::read(&gzi) {
+                Ok(index) => index,
+                Err(IntergeneError::Io { source, .. }) if source.kind() == ErrorKind::NotFound => {
+                    let index = GziIndex::build(file)?;
+                    let _ = index.write(&gzi);
+                    index
+                }
+                Err(e) => return Err(e),
+            })
+        } else {
+            None
+        };
+        Ok(IndexedFasta {
+            path: file.to_string(),
+            file: File::open(file).map_err(|e| IntergeneError::io(file, e))?,
+            gzi,
+            records,
+        })
+    }
+
+    /// The sequence with the given ID, if the FASTA file has it
+    pub fn get(&self, id: &str) -> Option<IndexedSeq<'_>> {
+        self.records
+            .iter()
+            .find(|record| record.name == id)
+            .map(|record| IndexedSeq {
+                fasta: self,
+                record,
+            })
+    }
+}
+
+/// A single sequence of an `IndexedFasta`
+pub struct IndexedSeq<'a> {
+    fasta: &'a IndexedFasta,
+    record: &'a FaiRecord,
+}
+
+impl SeqSource for IndexedSeq<'_> {
+    fn seq_id(&self) -> &str {
+        &self.record.name
+    }
+
+    fn seq_len(&self) -> usize {
+        self.record.length as usize
+    }
+
+    fn fetch(&self, start: usize, end: usize) -> Result<String, IntergeneError> {
+        if start > end || end > self.seq_len() {
+            return Err(self.out_of_bounds(start, end));
+        }
+        if start == end {
+            return Ok(String::new());
+        }
+        let record = self.record;
+        let position = |base: u64| {
+            record.offset + base / record.line_bases * record.line_width + base % record.line_bases
+        };
+        let first = position(start as u64);
+        let last = position(end as u64 - 1);
+        let mut bytes = vec![0; (last - first + 1) as usize];
+        let mut file = &self.fasta.file;
+        match &self.fasta.gzi {
+            Some(gzi) => gzi.read_at(file, first, &mut bytes),
+            None => file
+                .seek(SeekFrom::Start(first))
+                .and_then(|_| file.read_exact(&mut bytes)),
+        }
+        .map_err(|e| IntergeneError::io(&self.fasta.path, e))?;
+        bytes.retain(|b| *b != b'\n' && *b != b'\r');
+        String::from_utf8(bytes).map_err(|e| {
+            IntergeneError::io(&self.fasta.path, io::Error::new(ErrorKind::InvalidData, e))
+        })
+    }
+}
+
+/// The complement of a single (IUPAC) nucleotide, keeping its case
+/// Unknown characters (e.g gaps) are kept as they are
+pub fn complement(base: char) -> char {
+    let complement = match base.to_ascii_uppercase() {
+        'A' => 'T',
+        'T' | 'U' => 'A',
+        'G' => 'C',
+        'C' => 'G',
+        'R' => 'Y',
+        'Y' => 'R',
+        'K' => 'M',
+        'M' => 'K',
+        'B' => 'V',
+        'V' => 'B',
+        'D' => 'H',
+        'H' => 'D',
+        // S, W and N are their own complement
+        other => other,
+    };
+    if base.is_ascii_lowercase() {
+        complement.to_ascii_lowercase()
+    } else {
+        complement
+    }
+}
+
+/// The reverse complement of a (IUPAC) nucleotide sequence
+pub fn reverse_complement(seq: &str) -> String {
+    seq.chars().rev().map(complement).collect()
+}
+
+/// The sequence of an entry with `extend` extra bases on both sides (for context), read 5' -> 3'
+/// i.e reverse complemented for entries on the minus strand. Only the bases the entry needs are
+/// taken from `refseq`, see `SeqSource`
+/// Entries ending past the end of a circular sequence span its origin. The extension stops at the
+/// ends of a linear sequence and wraps around the origin of a circular one, but never takes more
+/// than the whole sequence
+/// Entries with invalid coordinates or (also when wrapping around the origin) outside of the
+/// sequence are an error, as are entries ending past the end of a linear sequence
+pub fn entry_seq<S: SeqSource + ?Sized>(
+    entry: &GffEntry,
+    refseq: &S,
+    extend: i64,
+    circular: bool,
+) -> Result<String, IntergeneError> {
+    let len = refseq.seq_len() as i64;
+    if entry.start < 1 || entry.start > entry.end {
+        return Err(IntergeneError::InvalidCoordinates {
+            seqid: entry.seqid.clone(),
+            start: entry.start,
+            end: entry.end,
+        });
+    }
+    let spans_origin = circular && entry.end - len < entry.start;
+    if entry.start > len || (entry.end > len && !spans_origin) {
+        return Err(IntergeneError::OutOfBounds {
+            seqid: entry.seqid.clone(),
+            start: entry.start,
+            end: entry.end,
+            length: len as usize,
+        });
+    }
+    let (start, end) = if circular {
+        let extend = extend.min((len - (entry.end - entry.start + 1)) / 2).max(0);
+        (entry.start - extend, entry.end + extend)
+    } else {
+        ((entry.start - extend).max(1), (entry.end + extend).min(len))
+    };
+
+    // Taken in pieces up to the end of the sequence, continuing at its start
+    let mut seq = String::new();
+    let mut position = start;
+    while position <= end {
+        let offset = (position - 1).rem_euclid(len);
+        let piece_end = (offset + end - position + 1).min(len);
+        seq.push_str(&refseq.fetch(offset as usize, piece_end as usize)?);
+        position += piece_end - offset;
+    }
+    Ok(match entry.strand.as_str() {
+        "-" => reverse_complement(&seq),
+        _ => seq,
+    })
+}
+
+// Given a vector of GFF entries, add the sequence to each entry (see `entry_seq`)
+pub fn add_seq_to_entries<S: SeqSource + ?Sized>(
+    entries: &mut [GffEntry],
+    refseq: &S,
+    extend: i64,
+    circular: bool,
+) -> Result<(), IntergeneError> {
+    for entry in entries {
+        let seq = entry_seq(entry, refseq, extend, circular)?;
+        entry.add_seq(seq);
+    }
+    Ok(())
+}
+
+/// The FASTA header template used if none is given, i.e all attributes and the length
+pub const DEFAULT_HEADER_TEMPLATE: &str = "{attributes} length: {length}";
+
+/// Fills in the placeholders of a FASTA header template for the given entry:
+/// the GFF columns `{seqid}`, `{source}`, `{type}`, `{start}`, `{end}`, `{score}`, `{strand}`,
+/// `{phase}` and `{attributes}`, the sequence `{length}` and any attribute by its key, e.g `{ID}`
+/// or `{locus_tag}`. Attributes the entry does not have are written as "."
+pub fn format_header(template: &str, entry: &GffEntry) -> String {
+    let mut header = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let close = match rest[open..].find('}') {
+            Some(close) => open + close,
+            None => break,
+        };
+        header.push_str(&rest[..open]);
+        let key = &rest[open + 1..close];
+        let value = match key {
+            "seqid" => entry.seqid.clone(),
+            "source" => entry.source.clone(),
+            "type" => entry.r#type.clone(),
+            "start" => entry.start.to_string(),
+            "end" => entry.end.to_string(),
+            "score" => entry.score.clone(),
+            "strand" => entry.strand.clone(),
+            "phase" => entry.phase.clone(),
+            "attributes" => entry.attributes.to_string(),
+            "length" => entry.seq.len().to_string(),
+            key => entry
+                .attributes
+                .get(key)
+                .map(|values| values.join(","))
+                .unwrap_or_else(|| ".".to_string()),
+        };
+        header.push_str(&value);
+        rest = &rest[close + 1..];
+    }
+    header.push_str(rest);
+    header
+}
+
+/// More generic fasta writer
+/// The header of each sequence is made from the `header_template` (see `format_header`)
+pub fn write_fasta_to_file(
+    entry_type: &str,
+    gff_entries: &[GffEntry],
+    filename: &str,
+    header_template: &str,
+    bgzip: bool,
+) -> Result<(), IntergeneError> {
+    let mut to_write = String::new();
+    // For entries matching type, write their sequences to a file
+    for entry in gff_entries {
+        if entry.r#type == entry_type {
+            let _ = writeln!(to_write, ">{}", format_header(header_template, entry));
+            let mut seq = entry.seq.clone();
+            while seq.len() > 80 {
+                let _ = writeln!(to_write, "{}", &seq[..80]);
+                // to_write.push_str(&format!("{}\n", &seq[..80]));
+                seq = seq[80..].to_string();
+            }
+            // to_write.push_str(&format!("{}\n", &seq));
+            let _ = writeln!(to_write, "{}", &seq);
+        }
+        // to_write.push_str(&format!(">{}\n{}\n", entry.attributes, entry.seq));
+    }
+    compression::write_file(filename, &to_write, bgzip)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complements_iupac_codes_keeping_case() {
+        assert_eq!(complement('A'), 'T');
+        assert_eq!(complement('u'), 'a');
+        assert_eq!(complement('r'), 'y');
+        assert_eq!(complement('K'), 'M');
+        assert_eq!(complement('b'), 'v');
+        assert_eq!(complement('D'), 'H');
+        // Their own complement, or no nucleotide at all
+        for base in ['S', 'w', 'N', '-', '*'] {
+            assert_eq!(complement(base), base);
+        }
+        assert_eq!(reverse_complement("ATGcgRYn-"), "-nRYcgCAT");
+        assert_eq!(reverse_complement(""), "");
+    }
+
+    #[test]
+    fn header_templates_fill_columns_and_attributes() {
+        let mut entry = crate::gff::tests::entry("CDS", 3, 8, "-", "ID=cds1;Dbxref=a,b");
+        entry.add_seq("ATGTAA".to_string());
+        assert_eq!(
+            format_header(DEFAULT_HEADER_TEMPLATE, &entry),
+            "ID=cds1;Dbxref=a,b length: 6"
+        );
+        assert_eq!(
+            format_header(
+                "{ID} {Dbxref} {seqid}:{start}-{end}({strand}) {type}",
+                &entry
+            ),
+            "cds1 a,b chr1:3-8(-) CDS"
+        );
+        // Missing attributes are ".", an unclosed placeholder is kept as it is
+        assert_eq!(format_header("{locus_tag} {ID", &entry), ". {ID");
+    }
+
+    #[test]
+    fn only_circular_sequences_wrap_around_the_origin() {
+        let refseq = Seq {
+            header: ">chr1".to_string(),
+            seq: "ACGTACGTAC".to_string(),
+        };
+        let gene = || vec![crate::gff::tests::entry("gene", 8, 12, "+", "ID=g1")];
+        assert!(matches!(
+            add_seq_to_entries(&mut gene(), &refseq, 0, false),
+            Err(IntergeneError::OutOfBounds { .. })
+        ));
+        let mut wrapped = gene();
+        add_seq_to_entries(&mut wrapped, &refseq, 0, true).unwrap();
+        assert_eq!(wrapped[0].seq, "TACAC");
+        // Not even a circular sequence wraps more than once
+        let mut too_long = vec![crate::gff::tests::entry("gene", 3, 13, "+", "ID=g2")];
+        assert!(add_seq_to_entries(&mut too_long, &refseq, 0, true).is_err());
+    }
+
+    #[test]
+    fn extension_wraps_around_the_origin_of_circular_sequences() {
+        let refseq = Seq {
+            header: ">chr1".to_string(),
+            seq: "ACGTACGTAC".to_string(),
+        };
+        let igr = crate::gff::tests::entry("intergenic", 1, 2, "+", "ID=IGR_1");
+        assert_eq!(entry_seq(&igr, &refseq, 2, false).unwrap(), "ACGT");
+        assert_eq!(entry_seq(&igr, &refseq, 2, true).unwrap(), "ACACGT");
+        // Never more than the whole sequence
+        assert_eq!(entry_seq(&igr, &refseq, 20, true).unwrap(), "GTACACGTAC");
+        let igr = crate::gff::tests::entry("intergenic", 9, 12, "-", "ID=IGR_2");
+        assert_eq!(entry_seq(&igr, &refseq, 1, true).unwrap(), "CGTGTA");
+
+        // An `extend` attribute of the input is no extension
+        let gene = crate::gff::tests::entry("gene", 3, 4, "+", "ID=g1;extend=2");
+        assert_eq!(entry_seq(&gene, &refseq, 0, false).unwrap(), "GT");
+    }
+
+    #[test]
+    fn indexed_fasta_fetches_across_lines() {
+        let dir = crate::gff::tests::TempDir::new("fai");
+        let fasta = &dir.file("test.fa");
+        fs::write(
+            fasta,
+            ">chr1 first\nACGTA\nCCGGT\nTT\n>chr2\r\nGGGG\r\nAA\r\n",
+        )
+        .unwrap();
+
+        let indexed = IndexedFasta::open(fasta).unwrap();
+        assert_eq!(
+            read_fai(&format!("{}.fai", fasta)).unwrap(),
+            indexed.records
+        );
+        let chr1 = indexed.get("chr1").unwrap();
+        assert_eq!(chr1.seq_len(), 12);
+        assert_eq!(chr1.fetch(3, 11).unwrap(), "TACCGGTT");
+        assert!(chr1.fetch(3, 13).is_err());
+        let chr2 = indexed.get("chr2").unwrap();
+        assert_eq!(chr2.fetch(0, 6).unwrap(), "GGGGAA");
+        assert!(indexed.get("chr3").is_none());
+
+        fs::write(fasta, ">chr1\nACG\nACGT\n").unwrap();
+        assert!(matches!(
+            build_fai(fasta),
+            Err(IntergeneError::Parse { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn bgzip_fasta_fetches_across_blocks() {
+        let dir = crate::gff::tests::TempDir::new("gzi");
+        let fasta = &dir.file("test.fa.gz");
+        // 100000 bases in lines of 60, more than one BGZF block
+        let seq: String = (0..100000)
+            .map(|i| ['A', 'C', 'G', 'T'][i * 7 % 13 % 4])
+            .collect();
+        let mut contents = ">chr1\n".to_string();
+        for line in seq.as_bytes().chunks(60) {
+            contents.push_str(std::str::from_utf8(line).unwrap());
+            contents.push('\n');
+        }
+        compression::write_file(fasta, &contents, true).unwrap();
+        assert!(compression::is_bgzf(fasta));
+
+        let indexed = IndexedFasta::open(fasta).unwrap();
+        let gzi = GziIndex::read(&format!("{}.gzi", fasta)).unwrap();
+        assert_eq!(Some(&gzi), indexed.gzi.as_ref());
+        let chr1 = indexed.get("chr1").unwrap();
+        assert_eq!(chr1.seq_len(), 100000);
+        for (start, end) in [(0, 10), (64000, 65000), (99990, 100000)] {
+            assert_eq!(chr1.fetch(start, end).unwrap(), seq[start..end]);
+        }
+    }
+}
@@ -0,0 +1,1030 @@
+use crate::compression;
+use crate::error::IntergeneError;
+use crate::fasta::{read_fasta_lines, Seq};
+use crate::hierarchy::FeatureTree;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::io::{self, BufRead, Lines};
+
+#[derive(Debug)]
+pub enum GFFErrors {
+    /// I/O error (e.g a corrupt gzip file) while reading the given line
+    Io(usize, io::Error),
+    /// The given line does not have 9 tab separated columns
+    GFFColumnMismatch(usize),
+    /// The start (column 4) or end (column 5) of the given line is not a valid integer
+    InvalidCoordinate(usize, usize),
+    /// The attribute column of the given line is not made up of `key=value` pairs
+    InvalidAttribute(usize),
+    /// The directive (`##...`) on the given line could not be parsed
+    InvalidDirective(usize),
+    /// The FASTA section (after `##FASTA`) starting at the given line could not be read
+    InvalidFasta(usize, io::Error),
+}
+
+impl GFFErrors {
+    /// Turns the error into an `IntergeneError::Parse` naming the file it occurred in
+    /// I/O errors become an `IntergeneError::Io` (keeping their cause) naming the file and line
+    pub fn in_file(self, path: &str) -> IntergeneError {
+        let (line, column, message) = match self {
+            GFFErrors::Io(line, source) | GFFErrors::InvalidFasta(line, source) => {
+                return IntergeneError::io(&format!("{}:{}", path, line), source)
+            }
+            GFFErrors::GFFColumnMismatch(line) => (line, None, "expected 9 tab separated columns"),
+            GFFErrors::InvalidCoordinate(line, column) => {
+                (line, Some(column), "the coordinate is not an integer")
+            }
+            GFFErrors::InvalidAttribute(line) => {
+                (line, Some(9), "the attributes are not key=value pairs")
+            }
+            GFFErrors::InvalidDirective(line) => (line, Some(1), "invalid directive"),
+        };
+        IntergeneError::parse(path, line, column, message)
+    }
+}
+
+/// The (decoded) attributes of a GFF entry, i.e column 9, as key -> values pairs in the order
+/// in which they appear in the file. Multiple values of a key are separated by commas in the file
+#[derive(Debug, Default, PartialEq, Eq, Ord, PartialOrd, Clone)]
+pub struct Attributes(Vec<(String, Vec<String>)>);
+
+impl Attributes {
+    /// Parses the attribute column of a GFF3 file, decoding URL escapes (e.g `%3B` -> `;`)
+    /// Returns None if any of the `;` separated pairs is not a `key=value` pair
+    pub fn parse(column: &str) -> Option<Attributes> {
+        let mut attributes = Attributes::default();
+        if column == "." {
+            return Some(attributes);
+        }
+        for pair in column.split(';') {
+            let pair = pair.trim();
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, values) = pair.split_once('=')?;
+            let values = values.split(',').map(percent_decode).collect();
+            attributes.0.push((percent_decode(key), values));
+        }
+        Some(attributes)
+    }
+
+    /// All values of the given key
+    pub fn get(&self, key: &str) -> Option<&[String]> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, values)| values.as_slice())
+    }
+
+    /// The first value of the given key
+    pub fn get_first(&self, key: &str) -> Option<&str> {
+        self.get(key)?.first().map(|v| v.as_str())
+    }
+
+    /// Sets the values of the given key, replacing the old ones if the key already exists
+    pub fn insert(&mut self, key: &str, values: Vec<String>) {
+        match self.0.iter_mut().find(|(k, _)| k == key) {
+            Some((_, old)) => *old = values,
+            None => self.0.push((key.to_string(), values)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Attributes {
+    /// Creates attributes with a single value per key
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Attributes(
+            iter.into_iter()
+                .map(|(k, v)| (k.into(), vec![v.into()]))
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for Attributes {
+    /// Writes the attributes as a GFF3 column 9, escaping reserved characters
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, ".");
+        }
+        for (i, (key, values)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ";")?;
+            }
+            write!(f, "{}=", percent_encode(key))?;
+            for (j, value) in values.iter().enumerate() {
+                if j > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{}", percent_encode(value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Attributes {
+    /// Parses the attribute column of a GTF (GFF2) file, i.e `gene_id "x"; transcript_id "y";`
+    /// Repeated keys (e.g `tag "basic"; tag "CCDS";`) are collected as multiple values
+    /// Quoted values may contain `;` and `\"`-escaped quotes
+    /// Returns None if any of the `;` separated pairs is not a `key value` pair
+    pub fn parse_gtf(column: &str) -> Option<Attributes> {
+        let mut attributes = Attributes::default();
+        if column == "." {
+            return Some(attributes);
+        }
+        for pair in split_gtf_pairs(column)? {
+            let pair = pair.trim();
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, value) = pair.split_once(|c: char| c.is_whitespace())?;
+            let value = value.trim();
+            let value = match value.strip_prefix('"') {
+                Some(quoted) => unescape_gtf(quoted.strip_suffix('"')?),
+                None => value.to_string(),
+            };
+            match attributes.0.iter_mut().find(|(k, _)| k == key) {
+                Some((_, values)) => values.push(value),
+                None => attributes.0.push((key.to_string(), vec![value])),
+            }
+        }
+        Some(attributes)
+    }
+
+    /// Writes the attributes as a GTF column 9, repeating keys that have multiple values
+    pub fn to_gtf_string(&self) -> String {
+        let mut column = String::new();
+        for (key, values) in &self.0 {
+            for value in values {
+                if !column.is_empty() {
+                    column.push(' ');
+                }
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                let _ = write!(column, "{} \"{}\";", key, value);
+            }
+        }
+        column
+    }
+}
+
+/// Splits a GTF attribute column at the `;` outside of quoted values
+/// Returns None if a quote is not closed
+fn split_gtf_pairs(column: &str) -> Option<Vec<&str>> {
+    let mut pairs: Vec<&str> = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (idx, c) in column.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                pairs.push(&column[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted {
+        return None;
+    }
+    pairs.push(&column[start..]);
+    Some(pairs)
+}
+
+/// Removes the backslashes escaping characters (i.e `"` and `\`) in a quoted GTF value
+fn unescape_gtf(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Decodes `%XX` escapes, leaving invalid escapes as they are
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // from_str_radix would also accept a sign, e.g "%+4"
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1..i + 3].iter().all(u8::is_ascii_hexdigit)
+        {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+            decoded.push(u8::from_str_radix(hex, 16).unwrap());
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escapes the characters that have a reserved meaning in the GFF3 attribute column
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            ';' | '=' | '&' | ',' | '%' | '\t' | '\n' | '\r' => {
+                let _ = write!(encoded, "%{:02X}", c as u8);
+            }
+            c if c.is_control() => {
+                let _ = write!(encoded, "%{:02X}", c as u32);
+            }
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+/// The flavour of a GFF file, which only differs in how the attribute column is written
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GffFormat {
+    /// GFF3, `key=value1,value2;key2=value`
+    Gff3,
+    /// GTF/GFF2, `key "value"; key2 "value";`
+    Gtf,
+}
+
+impl GffFormat {
+    /// Guesses the format from the file extension, anything but `.gtf`/`.gff2` is taken as GFF3
+    pub fn from_path(path: &str) -> GffFormat {
+        let path = path.to_lowercase();
+        let path = path.trim_end_matches(".gz");
+        if path.ends_with(".gtf") || path.ends_with(".gff2") {
+            GffFormat::Gtf
+        } else {
+            GffFormat::Gff3
+        }
+    }
+}
+
+/// A single line (feature) of a GFF file, optionally with the sequence it spans
+#[derive(Debug, PartialEq, Eq, Ord, PartialOrd, Clone)]
+pub struct GffEntry {
+    pub seqid: String,
+    pub source: String,
+    pub r#type: String,
+    pub start: i64,
+    pub end: i64,
+    pub score: String,
+    pub strand: String,
+    pub phase: String,
+    pub attributes: Attributes,
+    pub seq: String,
+}
+
+impl GffEntry {
+    pub fn add_seq(&mut self, seq: String) {
+        self.seq = seq;
+    }
+
+    /// A name to refer to this entry by, i.e its ID, gene_id, locus_tag or Name attribute (in
+    /// that order), falling back to its location if it has none of them
+    pub fn name(&self) -> String {
+        ["ID", "gene_id", "locus_tag", "Name"]
+            .iter()
+            .find_map(|key| self.attributes.get_first(key))
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("{}:{}-{}", self.seqid, self.start, self.end))
+    }
+
+    /// The ID that children link to: the `ID` attribute or, for GTF entries without one, the
+    /// `gene_id` of genes and the `transcript_id` of transcripts
+    pub fn id(&self) -> Option<&str> {
+        self.attributes
+            .get_first("ID")
+            .or_else(|| match self.r#type.as_str() {
+                "gene" => self.attributes.get_first("gene_id"),
+                "transcript" => self.attributes.get_first("transcript_id"),
+                _ => None,
+            })
+    }
+
+    /// The IDs of the parents of the entry: the `Parent` attribute or, for GTF entries without one,
+    /// the `gene_id` of transcripts and the `transcript_id` of everything else (e.g exons and CDS)
+    pub fn parent_ids(&self) -> Vec<&str> {
+        if let Some(parents) = self.attributes.get("Parent") {
+            return parents.iter().map(|parent| parent.as_str()).collect();
+        }
+        if self.attributes.get_first("ID").is_some() {
+            return Vec::new();
+        }
+        let key = match self.r#type.as_str() {
+            "gene" => return Vec::new(),
+            "transcript" => "gene_id",
+            _ => "transcript_id",
+        };
+        self.attributes.get_first(key).into_iter().collect()
+    }
+
+    /// Parses a single (non-comment) GFF3/GTF line, `line_number` is only used for errors
+    pub fn from_line(
+        line: &str,
+        line_number: usize,
+        format: GffFormat,
+    ) -> Result<GffEntry, GFFErrors> {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() != 9 {
+            return Err(GFFErrors::GFFColumnMismatch(line_number));
+        }
+        Ok(GffEntry {
+            seqid: parts[0].to_string(),
+            source: parts[1].to_string(),
+            r#type: parts[2].to_string(),
+            start: parts[3]
+                .parse::<i64>()
+                .map_err(|_| GFFErrors::InvalidCoordinate(line_number, 4))?,
+            end: parts[4]
+                .parse::<i64>()
+                .map_err(|_| GFFErrors::InvalidCoordinate(line_number, 5))?,
+            score: parts[5].to_string(),
+            strand: parts[6].to_string(),
+            phase: parts[7].to_string(),
+            attributes: match format {
+                GffFormat::Gff3 => Attributes::parse(parts[8]),
+                GffFormat::Gtf => Attributes::parse_gtf(parts[8]),
+            }
+            .ok_or(GFFErrors::InvalidAttribute(line_number))?,
+            seq: String::new(),
+        })
+    }
+}
+
+/// The extent of a sequence as given by a `##sequence-region seqid start end` directive
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SequenceRegion {
+    pub seqid: String,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl SequenceRegion {
+    /// Parses the arguments of a `##sequence-region` directive i.e `seqid start end`
+    fn parse(args: &str) -> Option<SequenceRegion> {
+        let mut args = args.split_whitespace();
+        let seqid = args.next()?.to_string();
+        let start = args.next()?.parse::<i64>().ok()?;
+        let end = args.next()?.parse::<i64>().ok()?;
+        Some(SequenceRegion { seqid, start, end })
+    }
+}
+
+/// A GFF file, consisting of the header (comment) lines, its entries and what the directives
+/// told us about the sequences, i.e their lengths and (if embedded after `##FASTA`) their sequence
+pub struct Gff {
+    pub header: String,
+    pub entries: Vec<GffEntry>,
+    pub sequence_regions: Vec<SequenceRegion>,
+    pub sequences: Vec<Seq>,
+}
+
+impl Gff {
+    /// The length of the given sequence according to its `##sequence-region` directive or, if
+    /// there is none, the end of its `region` feature
+    pub fn sequence_length(&self, seqid: &str) -> Option<i64> {
+        self.sequence_regions
+            .iter()
+            .find(|r| r.seqid == seqid)
+            .map(|r| r.end)
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .filter(|e| e.seqid == seqid && e.r#type == "region")
+                    .map(|e| e.end)
+                    .max()
+            })
+    }
+}
+
+/// Streams GFF entries from any reader, one line at a time
+/// The comment lines before the first entry are collected into the header. `##sequence-region`
+/// directives and the sequences of an embedded `##FASTA` section are collected as they are read.
+/// Use `next_group` to read the entries up to the next `###` directive, after which all
+/// forward references (i.e `Parent`s) are resolved.
+pub struct GffReader<R: BufRead> {
+    lines: Lines<R>,
+    format: GffFormat,
+    line_number: usize,
+    in_header: bool,
+    resolved: bool,
+    pending: Option<GffEntry>,
+    pub header: String,
+    pub sequence_regions: Vec<SequenceRegion>,
+    pub sequences: Vec<Seq>,
+}
+
+impl<R: BufRead> GffReader<R> {
+    pub fn new(reader: R) -> GffReader<R> {
+        GffReader::with_format(reader, GffFormat::Gff3)
+    }
+
+    pub fn with_format(reader: R, format: GffFormat) -> GffReader<R> {
+        GffReader {
+            lines: reader.lines(),
+            format,
+            line_number: 0,
+            in_header: true,
+            resolved: false,
+            pending: None,
+            header: String::new(),
+            sequence_regions: Vec::new(),
+            sequences: Vec::new(),
+        }
+    }
+
+    /// Reads the entries up to the next `###` directive (or the end of the entries), i.e a group
+    /// whose `Parent`s all refer to entries within it or the groups before
+    /// Returns None once all entries have been read
+    pub fn next_group(&mut self) -> Option<Result<Vec<GffEntry>, GFFErrors>> {
+        let mut group: Vec<GffEntry> = Vec::new();
+        loop {
+            match self.next() {
+                Some(Ok(entry)) => {
+                    // A `###` came before this entry, so it belongs to the next group
+                    if self.resolved && !group.is_empty() {
+                        self.resolved = false;
+                        self.pending = Some(entry);
+                        break;
+                    }
+                    self.resolved = false;
+                    group.push(entry);
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            }
+        }
+        if group.is_empty() {
+            None
+        } else {
+            Some(Ok(group))
+        }
+    }
+
+    /// Reads the rest of the input as FASTA, `first` being a header line already read
+    fn read_fasta(&mut self, first: Option<String>) -> Result<(), GFFErrors> {
+        let lines = first.into_iter().map(Ok).chain(&mut self.lines);
+        let mut sequences =
+            read_fasta_lines(lines).map_err(|e| GFFErrors::InvalidFasta(self.line_number, e))?;
+        self.sequences.append(&mut sequences);
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for GffReader<R> {
+    type Item = Result<GffEntry, GFFErrors>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(entry) = self.pending.take() {
+            return Some(Ok(entry));
+        }
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(GFFErrors::Io(self.line_number + 1, e))),
+            };
+            self.line_number += 1;
+
+            // Directives
+            if line.starts_with("##FASTA") || line.starts_with('>') {
+                let first = line.starts_with('>').then_some(line);
+                return self.read_fasta(first).err().map(Err);
+            }
+            if line.starts_with("###") {
+                self.resolved = true;
+                continue;
+            }
+            if let Some(args) = line.strip_prefix("##sequence-region") {
+                match SequenceRegion::parse(args) {
+                    Some(region) => self.sequence_regions.push(region),
+                    None => return Some(Err(GFFErrors::InvalidDirective(self.line_number))),
+                }
+            }
+
+            // Header info, and skip comments & empty lines
+            if line.starts_with('#') {
+                if self.in_header {
+                    let _ = writeln!(self.header, "{}", &line);
+                }
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            self.in_header = false;
+            return Some(GffEntry::from_line(&line, self.line_number, self.format));
+        }
+    }
+}
+
+/// Parses a GFF3 or GTF file (e.g as given by `GffFormat::from_path`), which may be gzip/bgzip
+/// compressed
+pub fn parse_gff(file: &str, format: GffFormat) -> Result<Gff, IntergeneError> {
+    // Take a filename and parse it into a GFF struct
+    let mut reader = GffReader::with_format(compression::open(file)?, format);
+    let entries = reader
+        .by_ref()
+        .collect::<Result<Vec<GffEntry>, GFFErrors>>()
+        .map_err(|e| e.in_file(file))?;
+    Ok(Gff {
+        header: reader.header,
+        entries,
+        sequence_regions: reader.sequence_regions,
+        sequences: reader.sequences,
+    })
+}
+
+/// A `key=value` filter on the attributes of entries, e.g `gene_biotype=rRNA`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AttributeFilter {
+    pub key: String,
+    pub value: String,
+}
+
+impl AttributeFilter {
+    /// Parses a filter given as `key=value`, None if there is no `=`
+    pub fn parse(filter: &str) -> Option<AttributeFilter> {
+        let (key, value) = filter.split_once('=')?;
+        Some(AttributeFilter {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    /// Whether any of the values of the entry's `key` attribute is `value`
+    pub fn matches(&self, entry: &GffEntry) -> bool {
+        entry
+            .attributes
+            .get(&self.key)
+            .is_some_and(|values| values.contains(&self.value))
+    }
+}
+
+/// Parses a GFF file and returns valid entry types i.e gene,CDS,transcript etc.
+pub fn get_valid_types(gff_entries: &[GffEntry]) -> Vec<String> {
+    let mut valid_types: Vec<String> = Vec::new();
+    for entry in gff_entries {
+        if !valid_types.contains(&entry.r#type) {
+            valid_types.push(entry.r#type.clone());
+        }
+    }
+    valid_types
+}
+
+/// Groups GFF entries by their seqid, keeping the order in which the seqids first appear
+pub fn group_by_seqid(gff_entries: &[GffEntry]) -> Vec<(&str, Vec<&GffEntry>)> {
+    let mut groups: Vec<(&str, Vec<&GffEntry>)> = Vec::new();
+    let mut indices: HashMap<&str, usize> = HashMap::new();
+    for entry in gff_entries {
+        let idx = *indices.entry(&entry.seqid).or_insert_with(|| {
+            groups.push((&entry.seqid, Vec::new()));
+            groups.len() - 1
+        });
+        groups[idx].1.push(entry);
+    }
+    groups
+}
+
+/// Whether the entries (of a single seqid) mark their sequence as circular, i.e have a `region`
+/// entry with `Is_circular=true`
+pub fn is_circular(gff_entries: &[GffEntry]) -> bool {
+    gff_entries
+        .iter()
+        .any(|e| e.r#type == "region" && e.attributes.get_first("Is_circular") == Some("true"))
+}
+
+// Create a new GFF file that includes the (intergenic) regions that we added
+pub fn write_gff_from_vec(
+    header: &str,
+    gff_entries: &[GffEntry],
+    fname: &str,
+    bgzip: bool,
+) -> Result<(), IntergeneError> {
+    // Recreate a gff file from the header, entries to include and write it to a file
+    let mut gff_file = String::new();
+    let _ = writeln!(gff_file, "{}\n", header);
+    // gff_file.push_str(&format!("{}\n", header));
+    for entry in gff_entries {
+        let _ = writeln!(
+            gff_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            entry.seqid,
+            entry.source,
+            entry.r#type,
+            entry.start,
+            entry.end,
+            entry.score,
+            entry.strand,
+            entry.phase,
+            entry.attributes,
+        );
+    }
+    compression::write_file(fname, &gff_file, bgzip)?;
+    Ok(())
+}
+
+/// Create a new GTF file from the entries, e.g to use with featureCounts or StringTie
+/// Entries without a `gene_id`/`transcript_id` (e.g from GFF3 input or the intergenic regions)
+/// get them from their `Parent` chain: the ID of the topmost ancestor (e.g the gene) is the gene
+/// id and the one below it (e.g the mRNA) the transcript id. Entries without parents use their
+/// own ID (or name, see `GffEntry::name`) for both
+pub fn write_gtf_from_vec(
+    header: &str,
+    gff_entries: &[GffEntry],
+    fname: &str,
+    bgzip: bool,
+) -> Result<(), IntergeneError> {
+    let mut gtf_file = String::new();
+    // The GFF3 version directive does not apply to GTF
+    for line in header.lines().filter(|l| !l.starts_with("##gff-version")) {
+        let _ = writeln!(gtf_file, "{}", line);
+    }
+    let tree = FeatureTree::new(gff_entries);
+    for entry in gff_entries {
+        // The entry and its ancestors up to the topmost one, following the first parent
+        let mut lineage: Vec<&GffEntry> = vec![entry];
+        while let Some(parent) = tree.parents(lineage[lineage.len() - 1]).first() {
+            // Parents that link back to the lineage would never end
+            if lineage.len() > gff_entries.len() {
+                break;
+            }
+            lineage.push(parent);
+        }
+        let id_of = |entry: &GffEntry| entry.id().map_or_else(|| entry.name(), |id| id.to_string());
+        let gene_id = id_of(lineage[lineage.len() - 1]);
+        let transcript_id = match lineage.len() {
+            1 => gene_id.clone(),
+            len => lineage[len - 2]
+                .id()
+                .map_or_else(|| gene_id.clone(), |id| id.to_string()),
+        };
+
+        // gene_id and transcript_id have to come first
+        let mut attributes = Attributes::default();
+        for (key, derived) in [("gene_id", gene_id), ("transcript_id", transcript_id)] {
+            let id = entry
+                .attributes
+                .get_first(key)
+                .map_or(derived, |id| id.to_string());
+            attributes.insert(key, vec![id]);
+        }
+        for (key, values) in entry.attributes.iter() {
+            if key != "gene_id" && key != "transcript_id" {
+                attributes.insert(key, values.to_vec());
+            }
+        }
+        let _ = writeln!(
+            gtf_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            entry.seqid,
+            entry.source,
+            entry.r#type,
+            entry.start,
+            entry.end,
+            entry.score,
+            entry.strand,
+            entry.phase,
+            attributes.to_gtf_string(),
+        );
+    }
+    compression::write_file(fname, &gtf_file, bgzip)?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An entry on chr1 for the tests of all modules, `attributes` is a GFF3 column 9
+    pub(crate) fn entry(
+        r#type: &str,
+        start: i64,
+        end: i64,
+        strand: &str,
+        attributes: &str,
+    ) -> GffEntry {
+        let line = format!(
+            "chr1\ttest\t{}\t{}\t{}\t.\t{}\t.\t{}",
+            r#type, start, end, strand, attributes
+        );
+        GffEntry::from_line(&line, 1, GffFormat::Gff3).unwrap()
+    }
+
+    /// A directory for the files of a test, which is removed when dropped (also if the test fails)
+    pub(crate) struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        /// `name` has to be unique among the tests, as they run in the same process
+        pub(crate) fn new(name: &str) -> TempDir {
+            let dir =
+                std::env::temp_dir().join(format!("intergene-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        /// The path of a file in the directory
+        pub(crate) fn file(&self, name: &str) -> String {
+            self.0.join(name).to_str().unwrap().to_string()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn decodes_escapes_and_multiple_values() {
+        let attributes = Attributes::parse("ID=g1;Note=a%3Bb%2Cc,d;Dbxref=x,y; Name=100%").unwrap();
+        assert_eq!(attributes.get_first("ID"), Some("g1"));
+        assert_eq!(
+            attributes.get("Note"),
+            Some(&["a;b,c".to_string(), "d".to_string()][..])
+        );
+        assert_eq!(attributes.get("Dbxref").unwrap().len(), 2);
+        // A trailing or incomplete escape is kept as it is
+        assert_eq!(attributes.get_first("Name"), Some("100%"));
+        assert_eq!(percent_decode("a%4"), "a%4");
+        assert_eq!(percent_decode("%zz%41"), "%zzA");
+        assert_eq!(percent_decode("%+4%-1"), "%+4%-1");
+        // Written back with the reserved characters escaped again
+        assert_eq!(
+            attributes.to_string(),
+            "ID=g1;Note=a%3Bb%2Cc,d;Dbxref=x,y;Name=100%25"
+        );
+        assert!(Attributes::parse("ID=g1;Note").is_none());
+    }
+
+    #[test]
+    fn dot_columns_are_kept() {
+        let entry =
+            GffEntry::from_line("chr1\t.\tgene\t1\t10\t.\t.\t.\t.", 1, GffFormat::Gff3).unwrap();
+        assert_eq!(entry.source, ".");
+        assert_eq!(entry.score, ".");
+        assert_eq!(entry.strand, ".");
+        assert_eq!(entry.phase, ".");
+        assert!(entry.attributes.is_empty());
+        assert_eq!(entry.attributes.to_string(), ".");
+        assert_eq!(entry.name(), "chr1:1-10");
+    }
+
+    #[test]
+    fn reads_directives_and_embedded_fasta() {
+        let gff = "##gff-version 3\n\
+            ##sequence-region chr1 1 20\n\
+            ##sequence-region pl1 1 8\n\
+            chr1\tsrc\tgene\t2\t5\t.\t+\t.\tID=g1\n\
+            ###\n\
+            # a comment between the entries\n\
+            pl1\tsrc\tgene\t1\t4\t.\t-\t.\tID=g2\n\
+            ###\n\
+            ##FASTA\n\
+            >chr1 chromosome\n\
+            ACGTACGTAC\n\
+            GTACGTACGT\n\
+            >pl1\n\
+            AAAACCCC\n";
+        let mut reader = GffReader::new(gff.as_bytes());
+        let entries = reader
+            .by_ref()
+            .collect::<Result<Vec<GffEntry>, GFFErrors>>()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            reader.header,
+            "##gff-version 3\n##sequence-region chr1 1 20\n##sequence-region pl1 1 8\n"
+        );
+        assert_eq!(
+            reader.sequence_regions[1],
+            SequenceRegion {
+                seqid: "pl1".to_string(),
+                start: 1,
+                end: 8
+            }
+        );
+        assert_eq!(reader.sequences.len(), 2);
+        assert_eq!(reader.sequences[0].id(), "chr1");
+        assert_eq!(reader.sequences[0].seq, "ACGTACGTACGTACGTACGT");
+
+        let gff = Gff {
+            header: reader.header,
+            entries,
+            sequence_regions: reader.sequence_regions,
+            sequences: reader.sequences,
+        };
+        assert_eq!(gff.sequence_length("pl1"), Some(8));
+        assert_eq!(gff.sequence_length("chr2"), None);
+    }
+
+    #[test]
+    fn groups_seqids_in_order_of_appearance() {
+        let mut entries = vec![
+            entry("gene", 1, 5, "+", "ID=g1"),
+            entry("gene", 1, 5, "+", "ID=g2"),
+            entry("gene", 7, 9, "+", "ID=g3"),
+        ];
+        entries[1].seqid = "plasmid".to_string();
+        let groups: Vec<(&str, Vec<&str>)> = group_by_seqid(&entries)
+            .into_iter()
+            .map(|(seqid, group)| (seqid, group.iter().filter_map(|e| e.id()).collect()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (entries[0].seqid.as_str(), vec!["g1", "g3"]),
+                ("plasmid", vec!["g2"])
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_end_at_forward_reference_markers() {
+        let gff = "chr1\tsrc\tgene\t2\t5\t.\t+\t.\tID=g1\n\
+            chr1\tsrc\tmRNA\t2\t5\t.\t+\t.\tID=m1;Parent=g1\n\
+            ###\n\
+            ###\n\
+            chr1\tsrc\tgene\t8\t9\t.\t+\t.\tID=g2\n\
+            # a comment within the group\n\
+            chr1\tsrc\tCDS\t8\t9\t.\t+\t0\tParent=g2\n\
+            ###\n\
+            chr1\tsrc\tgene\t11\t12\t.\t-\t.\tID=g3\n";
+        let mut reader = GffReader::new(gff.as_bytes());
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        while let Some(group) = reader.next_group() {
+            groups.push(group.unwrap().iter().map(|e| e.r#type.clone()).collect());
+        }
+        assert_eq!(
+            groups,
+            vec![vec!["gene", "mRNA"], vec!["gene", "CDS"], vec!["gene"]]
+        );
+        // Without any `###` all entries are one group
+        let mut reader = GffReader::new("chr1\tsrc\tgene\t2\t5\t.\t+\t.\tID=g1\n".as_bytes());
+        assert_eq!(reader.next_group().unwrap().unwrap().len(), 1);
+        assert!(reader.next_group().is_none());
+    }
+
+    #[test]
+    fn fasta_without_directive_and_invalid_regions() {
+        // Sequences may directly follow the entries without a ##FASTA line
+        let gff = "chr1\tsrc\tregion\t1\t12\t.\t+\t.\tID=chr1\n>chr1\nACGT\n";
+        let mut reader = GffReader::new(gff.as_bytes());
+        let entries: Vec<GffEntry> = reader.by_ref().map(|e| e.unwrap()).collect();
+        assert_eq!(reader.sequences[0].seq, "ACGT");
+        // Without a ##sequence-region the length comes from the region entry
+        let gff = Gff {
+            header: reader.header,
+            entries,
+            sequence_regions: reader.sequence_regions,
+            sequences: reader.sequences,
+        };
+        assert_eq!(gff.sequence_length("chr1"), Some(12));
+
+        let mut reader = GffReader::new("##gff-version 3\n##sequence-region chr1 1\n".as_bytes());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(GFFErrors::InvalidDirective(2)))
+        ));
+    }
+
+    #[test]
+    fn gtf_values_may_contain_separators_and_quotes() {
+        let column = r#"gene_id "g1"; note "a; b"; tag "basic"; tag "CCDS"; label "say \"hi\" \\o/"; level 2;"#;
+        let attributes = Attributes::parse_gtf(column).unwrap();
+        assert_eq!(attributes.get_first("note"), Some("a; b"));
+        assert_eq!(attributes.get("tag").unwrap().len(), 2);
+        assert_eq!(attributes.get_first("label"), Some(r#"say "hi" \o/"#));
+        assert_eq!(attributes.get_first("level"), Some("2"));
+        assert!(Attributes::parse_gtf(r#"gene_id "g1; transcript_id "t1";"#).is_none());
+        assert!(Attributes::parse_gtf("gene_id;").is_none());
+
+        let written = attributes.to_gtf_string();
+        assert_eq!(
+            written,
+            r#"gene_id "g1"; note "a; b"; tag "basic"; tag "CCDS"; label "say \"hi\" \\o/"; level "2";"#
+        );
+        assert_eq!(Attributes::parse_gtf(&written), Some(attributes));
+    }
+
+    #[test]
+    fn gtf_round_trips_through_the_writer() {
+        let dir = TempDir::new("gtf");
+        let file = &dir.file("test.gtf");
+        let mut igr = entry("intergenic", 11, 19, ".", "ID=IGR_1;Note=a%3B b");
+        igr.attributes
+            .insert("label", vec![r#"say "hi""#.to_string()]);
+        let mut exon = entry("exon", 1, 10, "+", ".");
+        exon.attributes = Attributes::parse_gtf(r#"gene_id "g1"; transcript_id "t1";"#).unwrap();
+        let gff3 = [
+            entry("gene", 21, 50, "+", "ID=g1"),
+            entry("mRNA", 21, 50, "+", "ID=m1;Parent=g1"),
+            entry("exon", 21, 30, "+", "Parent=m1"),
+            entry("CDS", 41, 50, "+", "ID=c1;Parent=g1"),
+            entry("exon", 51, 60, "+", "."),
+        ];
+        let mut entries = vec![exon.clone(), igr];
+        entries.extend(gff3);
+        write_gtf_from_vec("##gff-version 3\n#comment", &entries, file, false).unwrap();
+
+        let gtf = parse_gff(file, GffFormat::from_path(file)).unwrap();
+        assert_eq!(gtf.header, "#comment\n");
+        assert_eq!(gtf.entries[0], exon);
+        // Entries without gene_id/transcript_id get their ID as both
+        assert_eq!(
+            gtf.entries[1].attributes.to_gtf_string(),
+            r#"gene_id "IGR_1"; transcript_id "IGR_1"; ID "IGR_1"; Note "a; b"; label "say \"hi\"";"#
+        );
+        // The others get them from their Parent chain
+        let ids: Vec<(&str, &str)> = gtf.entries[2..]
+            .iter()
+            .map(|e| {
+                (
+                    e.attributes.get_first("gene_id").unwrap(),
+                    e.attributes.get_first("transcript_id").unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                ("g1", "g1"),
+                ("g1", "m1"),
+                ("g1", "m1"),
+                ("g1", "c1"),
+                ("chr1:51-60", "chr1:51-60")
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_match_any_value_of_the_key() {
+        let filter = AttributeFilter::parse("gene_biotype=rRNA").unwrap();
+        assert_eq!(filter.key, "gene_biotype");
+        assert!(filter.matches(&entry("gene", 1, 9, "+", "gene_biotype=tRNA,rRNA")));
+        assert!(!filter.matches(&entry("gene", 1, 9, "+", "gene_biotype=rRNA_pseudo")));
+        assert!(!filter.matches(&entry("gene", 1, 9, "+", "ID=g1")));
+        // Only split at the first =
+        assert_eq!(AttributeFilter::parse("note=a=b").unwrap().value, "a=b");
+        assert!(AttributeFilter::parse("gene_biotype").is_none());
+    }
+
+    #[test]
+    fn errors_name_line_and_column() {
+        let error = |line: &str| GffEntry::from_line(line, 7, GffFormat::Gff3).unwrap_err();
+        assert!(matches!(
+            error("chr1\tsrc\tgene\tx\t10\t.\t+\t.\tID=g1"),
+            GFFErrors::InvalidCoordinate(7, 4)
+        ));
+        assert!(matches!(
+            error("chr1\tsrc\tgene\t1\t1e3\t.\t+\t.\tID=g1"),
+            GFFErrors::InvalidCoordinate(7, 5)
+        ));
+        assert!(matches!(
+            error("chr1\tsrc\tgene\t1\t10\t.\t+\t.\tID"),
+            GFFErrors::InvalidAttribute(7)
+        ));
+        assert!(matches!(
+            error("chr1 src gene 1 10 . + . ID=g1"),
+            GFFErrors::GFFColumnMismatch(7)
+        ));
+
+        let error = error("chr1\tsrc\tgene\tx\t10\t.\t+\t.\tID=g1").in_file("a.gff");
+        assert_eq!(
+            error.to_string(),
+            "a.gff:7:4: the coordinate is not an integer"
+        );
+        let error = GFFErrors::InvalidAttribute(2).in_file("a.gff");
+        assert!(matches!(
+            error,
+            IntergeneError::Parse {
+                line: 2,
+                column: Some(9),
+                ..
+            }
+        ));
+
+        // I/O errors keep their cause
+        let mut reader = GffReader::new(&[0xff, b'\n'][..]);
+        let error = reader.next().unwrap().unwrap_err().in_file("a.gff");
+        assert!(matches!(&error, IntergeneError::Io { path, .. } if path == "a.gff:1"));
+        assert!(std::error::Error::source(&error).is_some());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::gff::{Attributes, GffEntry};
+use std::collections::HashMap;
+
+/// The parent/child relationships between entries, as given by their `ID` and `Parent` attributes
+/// (or `gene_id`/`transcript_id` in GTF, see `GffEntry::id` and `GffEntry::parent_ids`)
+/// Entries can have multiple parents (e.g an exon shared by two transcripts), and multiple entries
+/// can share an ID (e.g the CDS lines of a single spliced CDS)
+pub struct FeatureTree<'a> {
+    entries: &'a [GffEntry],
+    by_id: HashMap<&'a str, Vec<usize>>,
+    children: HashMap<&'a str, Vec<usize>>,
+}
+
+impl<'a> FeatureTree<'a> {
+    pub fn new(entries: &'a [GffEntry]) -> FeatureTree<'a> {
+        let mut by_id: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut children: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (idx, entry) in entries.iter().enumerate() {
+            if let Some(id) = entry.id() {
+                by_id.entry(id).or_default().push(idx);
+            }
+            for parent in entry.parent_ids() {
+                children.entry(parent).or_default().push(idx);
+            }
+        }
+        FeatureTree {
+            entries,
+            by_id,
+            children,
+        }
+    }
+
+    /// The entries with the given ID
+    pub fn get(&self, id: &str) -> Vec<&'a GffEntry> {
+        self.indices_to_entries(self.by_id.get(id))
+    }
+
+    /// The direct children of the entry with the given ID
+    pub fn children(&self, id: &str) -> Vec<&'a GffEntry> {
+        self.indices_to_entries(self.children.get(id))
+    }
+
+    /// The direct children of the entry with the given ID that are of the given type
+    pub fn children_of_type(&self, id: &str, r#type: &str) -> Vec<&'a GffEntry> {
+        self.children(id)
+            .into_iter()
+            .filter(|child| child.r#type == r#type)
+            .collect()
+    }
+
+    /// The direct parents of the given entry
+    pub fn parents(&self, entry: &GffEntry) -> Vec<&'a GffEntry> {
+        entry
+            .parent_ids()
+            .into_iter()
+            .flat_map(|parent| self.get(parent))
+            .collect()
+    }
+
+    /// The children of the given type (e.g exon or CDS) of an entry (e.g a transcript) in the
+    /// order they are transcribed, i.e by start for + and reversed for - strand entries
+    pub fn ordered_children(&self, entry: &GffEntry, r#type: &str) -> Vec<&'a GffEntry> {
+        let mut children = match entry.id() {
+            Some(id) => self.children_of_type(id, r#type),
+            None => Vec::new(),
+        };
+        children.sort_by_key(|child| child.start);
+        if entry.strand == "-" {
+            children.reverse();
+        }
+        children
+    }
+
+    /// The spliced sequence of an entry, i.e the (already extracted) sequences of its children of
+    /// the given type concatenated in transcription order. None if it has no such children
+    /// For CDS children the bases before the first codon (the phase of the first CDS) are left out
+    pub fn spliced_seq(&self, entry: &GffEntry, r#type: &str) -> Option<String> {
+        let children = self.ordered_children(entry, r#type);
+        let first = children.first()?;
+        let seq: String = children.iter().map(|child| child.seq.as_str()).collect();
+        let phase = match r#type {
+            "CDS" => first.phase.parse::<usize>().unwrap_or(0),
+            _ => 0,
+        };
+        Some(seq.get(phase..).unwrap_or("").to_string())
+    }
+
+    /// Intron entries for every entry (e.g transcript) with exon children, i.e the gaps between
+    /// its exons. They are numbered in transcription order and linked to the entry via `Parent`
+    /// (and the `gene_id`/`transcript_id` of GTF transcripts)
+    pub fn introns(&self) -> Vec<GffEntry> {
+        let mut introns: Vec<GffEntry> = Vec::new();
+        for transcript in self.entries {
+            let id = match transcript.id() {
+                Some(id) => id,
+                None => continue,
+            };
+            let mut exons = self.children_of_type(id, "exon");
+            exons.sort_by_key(|exon| exon.start);
+
+            // Gaps between the exons, overlapping exons leave no gap
+            let mut gaps: Vec<(i64, i64)> = Vec::new();
+            let mut last_end: Option<i64> = None;
+            for exon in exons {
+                if let Some(last_end) = last_end.filter(|end| exon.start > end + 1) {
+                    gaps.push((last_end + 1, exon.start - 1));
+                }
+                last_end = Some(last_end.map_or(exon.end, |end| end.max(exon.end)));
+            }
+            if transcript.strand == "-" {
+                gaps.reverse();
+            }
+
+            for (idx, (start, end)) in gaps.into_iter().enumerate() {
+                let intron_id = format!("{}_intron_{}", id, idx + 1);
+                let mut attributes: Attributes = [("ID", intron_id), ("Parent", id.to_string())]
+                    .into_iter()
+                    .collect();
+                for key in ["gene_id", "transcript_id"] {
+                    if let Some(value) = transcript.attributes.get_first(key) {
+                        attributes.insert(key, vec![value.to_string()]);
+                    }
+                }
+                introns.push(GffEntry {
+                    seqid: transcript.seqid.clone(),
+                    source: "intergene-finder".to_string(),
+                    r#type: "intron".to_string(),
+                    start,
+                    end,
+                    score: ".".to_string(),
+                    strand: transcript.strand.clone(),
+                    phase: ".".to_string(),
+                    attributes,
+                    seq: String::new(),
+                });
+            }
+        }
+        introns
+    }
+
+    fn indices_to_entries(&self, indices: Option<&Vec<usize>>) -> Vec<&'a GffEntry> {
+        indices
+            .map(|indices| indices.iter().map(|idx| &self.entries[*idx]).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gff::tests::entry;
+
+    #[test]
+    fn splices_minus_strand_exons_in_transcription_order() {
+        let mut entries = vec![
+            entry("mRNA", 1, 30, "-", "ID=mrna1"),
+            entry("exon", 1, 5, "-", "ID=exon1;Parent=mrna1"),
+            entry("exon", 20, 30, "-", "ID=exon2;Parent=mrna1,mrna2"),
+            entry("exon", 10, 15, "-", "ID=exon3;Parent=mrna1"),
+        ];
+        for (entry, seq) in entries.iter_mut().zip(["", "CCCCC", "AAAAA", "GGGGG"]) {
+            entry.add_seq(seq.to_string());
+        }
+        let tree = FeatureTree::new(&entries);
+        assert_eq!(
+            tree.spliced_seq(&entries[0], "exon"),
+            Some("AAAAAGGGGGCCCCC".to_string())
+        );
+        assert_eq!(tree.spliced_seq(&entries[0], "CDS"), None);
+        assert_eq!(tree.parents(&entries[2]).len(), 1);
+    }
+
+    #[test]
+    fn introns_are_the_gaps_between_exons() {
+        let entries = vec![
+            entry("mRNA", 1, 30, "-", "ID=mrna1"),
+            entry("exon", 1, 5, "-", "ID=exon1;Parent=mrna1"),
+            entry("exon", 20, 30, "-", "ID=exon2;Parent=mrna1"),
+            entry("exon", 10, 15, "-", "ID=exon3;Parent=mrna1"),
+            entry("exon", 6, 8, "-", "ID=exon4;Parent=mrna2"),
+        ];
+        let introns: Vec<(i64, i64, String)> = FeatureTree::new(&entries)
+            .introns()
+            .iter()
+            .map(|i| (i.start, i.end, i.attributes.to_string()))
+            .collect();
+        assert_eq!(
+            introns,
+            vec![
+                (16, 19, "ID=mrna1_intron_1;Parent=mrna1".to_string()),
+                (6, 9, "ID=mrna1_intron_2;Parent=mrna1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn gtf_exons_belong_to_their_transcript() {
+        let gtf = |r#type: &str, start: i64, end: i64, attributes: &str| {
+            let mut entry = entry(r#type, start, end, "+", ".");
+            entry.attributes = Attributes::parse_gtf(attributes).unwrap();
+            entry
+        };
+        let mut entries = vec![
+            gtf("gene", 1, 30, r#"gene_id "g1";"#),
+            gtf("transcript", 1, 30, r#"gene_id "g1"; transcript_id "t1";"#),
+            gtf("exon", 1, 5, r#"gene_id "g1"; transcript_id "t1";"#),
+            gtf("exon", 20, 30, r#"gene_id "g1"; transcript_id "t1";"#),
+        ];
+        for (entry, seq) in entries.iter_mut().zip(["", "", "CCCCC", "AAAAA"]) {
+            entry.add_seq(seq.to_string());
+        }
+        let tree = FeatureTree::new(&entries);
+        assert_eq!(tree.parents(&entries[1]), vec![&entries[0]]);
+        assert_eq!(
+            tree.spliced_seq(&entries[1], "exon"),
+            Some("CCCCCAAAAA".to_string())
+        );
+        let introns = tree.introns();
+        assert_eq!(introns.len(), 1);
+        assert_eq!((introns[0].start, introns[0].end), (6, 19));
+        assert_eq!(
+            introns[0].attributes.to_gtf_string(),
+            r#"ID "t1_intron_1"; Parent "t1"; gene_id "g1"; transcript_id "t1";"#
+        );
+    }
+}
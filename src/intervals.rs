@@ -0,0 +1,930 @@
+//! Finding the intergenic regions between the (genic) entries of a GFF file
+//!
+//! All coordinates in this module follow the GFF convention: 1-based and closed, i.e a region
+//! `(start, end)` covers the bases `start..=end` and is `end - start + 1` bases long. The first
+//! base of a sequence of length `len` is 1 and the last one `len`.
+//!
+//! - Genic entries are merged into non-overlapping genic blocks (see `merge_entries`)
+//! - The region between two blocks is `(previous.end + 1, next.start - 1)`, the one before the
+//!   first block is `(1, first.start - 1)` and the one after the last block `(last.end + 1, len)`
+//! - Only circular sequences have a region with `end > len`, the one spanning the origin
+//! - Likewise only entries on circular sequences may end past `len`, such an entry also covers
+//!   the bases `1..=end - len` at the start of the sequence
+//!
+//! So intergenic regions never overlap a genic base and, together with the genic blocks, tile the
+//! whole sequence (as long as no regions are dropped by `buffer`, trimming or length filters).
+
+use crate::gff::{Attributes, GffEntry};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The relative orientation of the two genes flanking an intergenic region
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Orientation {
+    /// → IGR ←, i.e both genes end at the IGR (terminators)
+    Convergent,
+    /// ← IGR →, i.e both genes start at the IGR (promoters)
+    Divergent,
+    /// → IGR →
+    CodirectionalForward,
+    /// ← IGR ←
+    CodirectionalReverse,
+}
+
+impl Orientation {
+    /// Classifies the orientation from the strands of the left and right flanking gene
+    /// Returns None if either of them is unstranded
+    pub fn from_strands(left: &str, right: &str) -> Option<Orientation> {
+        match (left, right) {
+            ("+", "-") => Some(Orientation::Convergent),
+            ("-", "+") => Some(Orientation::Divergent),
+            ("+", "+") => Some(Orientation::CodirectionalForward),
+            ("-", "-") => Some(Orientation::CodirectionalReverse),
+            _ => None,
+        }
+    }
+
+    /// The name used for the `orientation` attribute
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Orientation::Convergent => "convergent",
+            Orientation::Divergent => "divergent",
+            Orientation::CodirectionalForward => "codirectional_forward",
+            Orientation::CodirectionalReverse => "codirectional_reverse",
+        }
+    }
+}
+
+/// An intergenic region along with the genic entries to its left (lower coordinates) and right
+/// There is no left/right entry for the regions at the start/end of the sequence
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IntergenicRegion {
+    pub start: i64,
+    pub end: i64,
+    pub left: Option<GffEntry>,
+    pub right: Option<GffEntry>,
+}
+
+impl IntergenicRegion {
+    /// The orientation of the flanking genes, None if one is missing or unstranded
+    pub fn orientation(&self) -> Option<Orientation> {
+        match (&self.left, &self.right) {
+            (Some(left), Some(right)) => Orientation::from_strands(&left.strand, &right.strand),
+            _ => None,
+        }
+    }
+}
+
+/// The ID template used if none is given, e.g `IGR_NC_000913.3_191_336`
+pub const DEFAULT_ID_TEMPLATE: &str = "IGR_{seqid}_{start}_{end}";
+
+/// Fills in the placeholders of an ID template for the given region:
+/// `{seqid}`, `{start}`, `{end}`, `{strand}` (plus, minus or both) and `{left}`/`{right}`, the
+/// locus_tag (or name, see `GffEntry::name`) of the flanking genes ("none" at sequence ends)
+pub fn format_id(template: &str, region: &IntergenicRegion, seqid: &str, strand: &str) -> String {
+    let flank = |entry: &Option<GffEntry>| match entry {
+        Some(entry) => entry
+            .attributes
+            .get_first("locus_tag")
+            .map(|tag| tag.to_string())
+            .unwrap_or_else(|| entry.name()),
+        None => "none".to_string(),
+    };
+    let strand = match strand {
+        "+" => "plus",
+        "-" => "minus",
+        _ => "both",
+    };
+    template
+        .replace("{seqid}", seqid)
+        .replace("{start}", &region.start.to_string())
+        .replace("{end}", &region.end.to_string())
+        .replace("{strand}", strand)
+        .replace("{left}", &flank(&region.left))
+        .replace("{right}", &flank(&region.right))
+}
+
+/// For each intergenic region create a GFFEntry that has the same format as the other entries
+/// mainly start, end, type, and attributes i.e (ID,Name,locus_tag) defined, along with the
+/// flanking genes (left_gene,right_gene) and their orientation
+/// The ID is made from the `id_template` (see `format_id`), IDs that were already used (as
+/// counted in `used_ids`) get a `_2`, `_3`, ... suffix so they stay unique
+pub fn create_intergenic_entries(
+    intergenic_regions: Vec<IntergenicRegion>,
+    seqid: String,
+    strand: &str,
+    id_template: &str,
+    used_ids: &mut HashMap<String, usize>,
+) -> Vec<GffEntry> {
+    let intergenic_entries: Vec<GffEntry> = intergenic_regions
+        .iter()
+        .map(|region| {
+            let seq = String::from("");
+            let mut id = format_id(id_template, region, &seqid, strand);
+            let count = used_ids.entry(id.clone()).or_insert(0);
+            *count += 1;
+            if *count > 1 {
+                id = format!("{}_{}", id, count);
+            }
+            let mut attributes: Attributes =
+                [("ID", id.clone()), ("Name", id.clone()), ("locus_tag", id)]
+                    .into_iter()
+                    .collect();
+            if let Some(left) = &region.left {
+                attributes.insert("left_gene", vec![left.name()]);
+            }
+            if let Some(right) = &region.right {
+                attributes.insert("right_gene", vec![right.name()]);
+            }
+            if let Some(orientation) = region.orientation() {
+                attributes.insert("orientation", vec![orientation.as_str().to_string()]);
+            }
+            GffEntry {
+                // same seqid as the rest
+                seqid: seqid.clone(),
+                source: "intergene-finder".to_string(),
+                r#type: "intergenic".to_string(),
+                start: region.start,
+                end: region.end,
+                score: ".".to_string(),
+                strand: strand.to_string(),
+                phase: ".".to_string(),
+                attributes,
+                seq,
+            }
+        })
+        .collect();
+    intergenic_entries
+}
+
+/// Which features are created from the flanks of the genes next to an intergenic region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlankType {
+    /// `five_prime_UTR` upstream of the start and `three_prime_UTR` downstream of the stop
+    Utr,
+    /// Only an `upstream_region` (e.g promoter) upstream of the start
+    Upstream,
+}
+
+/// Creates the flanking features (see `FlankType`) of up to `length` bases for the genes on
+/// either side of each intergenic region. They are clipped to the intergenic region, have the
+/// strand of their gene and are linked to it via `Parent` (if it has an ID)
+/// Genes without a strand (".") have no up-/downstream and get no flanks
+/// Flanks entirely past the end `len` of a circular sequence (i.e after the origin) are moved to
+/// its start, so that `start <= len`
+pub fn create_flanking_entries(
+    intergenic_regions: &[IntergenicRegion],
+    seqid: &str,
+    length: i64,
+    flank_type: FlankType,
+    len: i64,
+) -> Vec<GffEntry> {
+    let mut flanks: Vec<GffEntry> = Vec::new();
+    if length < 1 {
+        return flanks;
+    }
+    for region in intergenic_regions {
+        let clipped_start = (region.end - length + 1).max(region.start);
+        let clipped_end = (region.start + length - 1).min(region.end);
+        // The gene to the left ends at the start of the region, the one to the right starts at its end
+        let sides = [
+            (&region.left, region.start, clipped_end, "-"),
+            (&region.right, clipped_start, region.end, "+"),
+        ];
+        for (gene, start, end, upstream_strand) in sides {
+            let gene = match gene {
+                Some(gene) if gene.strand == "+" || gene.strand == "-" => gene,
+                _ => continue,
+            };
+            let r#type = match (flank_type, gene.strand == upstream_strand) {
+                (FlankType::Utr, true) => "five_prime_UTR",
+                (FlankType::Utr, false) => "three_prime_UTR",
+                (FlankType::Upstream, true) => "upstream_region",
+                (FlankType::Upstream, false) => continue,
+            };
+            let (start, end) = if start > len {
+                (start - len, end - len)
+            } else {
+                (start, end)
+            };
+            let mut attributes: Attributes = [("ID", format!("{}_{}", gene.name(), r#type))]
+                .into_iter()
+                .collect();
+            if let Some(parent) = gene.attributes.get_first("ID") {
+                attributes.insert("Parent", vec![parent.to_string()]);
+            }
+            flanks.push(GffEntry {
+                seqid: seqid.to_string(),
+                source: "intergene-finder".to_string(),
+                r#type: r#type.to_string(),
+                start,
+                end,
+                score: ".".to_string(),
+                strand: gene.strand.clone(),
+                phase: ".".to_string(),
+                attributes,
+                seq: String::new(),
+            });
+        }
+    }
+    flanks
+}
+
+/// Which entry types count as genic when finding intergenic regions
+/// If `include` is given only those types are genic, otherwise every type apart from `region`
+/// and `sequence_feature` is. Types in `exclude` are never genic
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct GenicTypes {
+    pub include: Option<Vec<String>>,
+    pub exclude: Vec<String>,
+}
+
+impl GenicTypes {
+    /// The types that are not genic unless explicitly included
+    pub const DEFAULT_EXCLUDE: [&'static str; 2] = ["region", "sequence_feature"];
+
+    pub fn is_genic(&self, r#type: &str) -> bool {
+        if self.exclude.iter().any(|t| t == r#type) {
+            return false;
+        }
+        match &self.include {
+            Some(include) => include.iter().any(|t| t == r#type),
+            None => !GenicTypes::DEFAULT_EXCLUDE.contains(&r#type),
+        }
+    }
+}
+
+impl fmt::Display for GenicTypes {
+    /// Describes the chosen types, e.g for the header of the output
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.include {
+            Some(include) => write!(f, "genic-types={}", include.join(","))?,
+            None => write!(f, "genic-types=all")?,
+        }
+        let mut exclude: Vec<&str> = self.exclude.iter().map(|t| t.as_str()).collect();
+        if self.include.is_none() {
+            exclude.extend(GenicTypes::DEFAULT_EXCLUDE);
+        }
+        if !exclude.is_empty() {
+            write!(f, " ignore-types={}", exclude.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// A stretch of genic sequence, i.e the union of one or more overlapping (or nested) entries
+/// `first` is the outermost entry at the start of the block and `last` the one at its end
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GenicBlock<'a> {
+    pub start: i64,
+    pub end: i64,
+    pub first: &'a GffEntry,
+    pub last: &'a GffEntry,
+}
+
+/// Merges the entries into non-overlapping blocks of genic sequence, sorted by start
+/// Entries that overlap, are nested within another or directly follow each other (i.e no base in
+/// between) end up in the same block. All entries are expected to be on the same seqid
+pub fn merge_entries<'a>(entries: &[&'a GffEntry]) -> Vec<GenicBlock<'a>> {
+    // Sort by start, longest first, so the outermost entry (e.g gene before mRNA before CDS)
+    // comes first
+    let mut sorted: Vec<&GffEntry> = entries.to_vec();
+    sorted.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+
+    let mut blocks: Vec<GenicBlock> = Vec::new();
+    for entry in sorted {
+        match blocks.last_mut() {
+            Some(block) if entry.start <= block.end + 1 => {
+                // Only a strictly longer entry takes over, keeping the outermost one on ties
+                if entry.end > block.end {
+                    block.end = entry.end;
+                    block.last = entry;
+                }
+            }
+            _ => blocks.push(GenicBlock {
+                start: entry.start,
+                end: entry.end,
+                first: entry,
+                last: entry,
+            }),
+        }
+    }
+    blocks
+}
+
+/// Given a (vector of) GFF entry struct(s) return the intergenic regions, i.e their start and
+/// end along with the entries flanking them
+/// The entries are merged into genic blocks first (see `merge_entries`), so they do not need to
+/// be sorted and overlapping/nested entries are handled. A region is only reported if the
+/// distance between the flanking blocks (`next.start - previous.end`) is at least `buffer`
+/// For strand specific regions (strand "+" or "-") only entries on that strand (or unstranded
+/// ones) are taken into account. Start is always <= end in GFF, regardless of strand
+/// Only entries of a genic type (see `GenicTypes`) are taken into account
+/// For circular sequences the gaps before the first and after the last genic block are one region
+/// spanning the origin, which (as in GFF3) ends past the end of the sequence, i.e `end > length`
+pub fn get_intergenic_regions(
+    gff: &[GffEntry],
+    end: i64,
+    buffer: i64,
+    strand: &str,
+    genic_types: &GenicTypes,
+    circular: bool,
+) -> Vec<IntergenicRegion> {
+    // Filter if stranded and given, otherwise just go through all the entries i.e non-strand-specific IGRs
+    let filtered = gff
+        .iter()
+        .filter(|x| match strand {
+            "+" | "-" => x.strand == strand || x.strand == ".",
+            _ => true,
+        })
+        .filter(|x| genic_types.is_genic(&x.r#type))
+        .collect::<Vec<&GffEntry>>();
+    let blocks = merge_entries(&filtered);
+
+    // We obtain all the intergenic regions by going through the gaps between the genic blocks,
+    // position 0 being the "end" before the sequence and end + 1 the "start" after it
+    // On a circular sequence the part of a block past the end covers the start of the sequence,
+    // the last block ends furthest as the blocks do not overlap
+    let spans_origin = circular && blocks.last().is_some_and(|block| block.end > end);
+    let (mut last_end, mut last_entry) = match blocks.last() {
+        Some(block) if spans_origin => (block.end - end, Some(block.last)),
+        _ => (0, None),
+    };
+    let mut regions: Vec<IntergenicRegion> = Vec::new();
+    for block in &blocks {
+        // The region before the first block is part of the one across the origin if circular
+        let wraps = circular && last_entry.is_none();
+        // if next entry is less than (i.e within) Xnt of previous end/entry, do not mark it as an intergenic
+        if !wraps && block.start > last_end + 1 && block.start - last_end >= buffer {
+            regions.push(IntergenicRegion {
+                start: last_end + 1,
+                end: block.start - 1,
+                left: last_entry.cloned(),
+                right: Some(block.first.clone()),
+            });
+        }
+        // Blocks within the part of the last block past the origin leave it as it is
+        if block.end > last_end {
+            last_end = block.end;
+            last_entry = Some(block.last);
+        }
+    }
+    // The region across the origin, from after the last block up to the first block
+    // There is none if the last block spans the origin, the gaps after its end were added above
+    if let (true, Some(first)) = (circular, blocks.first()) {
+        let wrap_end = end + first.start - 1;
+        if !spans_origin && wrap_end > last_end && wrap_end + 1 - last_end >= buffer {
+            // Entirely before the first block if the last block ends at the end
+            let offset = if last_end >= end { end } else { 0 };
+            regions.push(IntergenicRegion {
+                start: last_end + 1 - offset,
+                end: wrap_end - offset,
+                left: last_entry.cloned(),
+                right: Some(first.first.clone()),
+            });
+        }
+        return regions;
+    }
+    // Fill in the last if the end of the gff hasnt reached the end refernece sequence
+    if end > last_end && end + 1 - last_end >= buffer {
+        regions.push(IntergenicRegion {
+            start: last_end + 1,
+            end,
+            left: last_entry.cloned(),
+            right: None,
+        });
+    }
+    regions
+}
+
+/// Shrinks the regions by `trim` nt on each side that borders a gene, i.e has a flanking entry
+/// Regions that are trimmed away completely are dropped
+/// A region across the origin of a circular sequence of length `len` that is trimmed to after the
+/// origin is moved to the start of the sequence, so that `start <= len`
+pub fn trim_regions(regions: Vec<IntergenicRegion>, trim: i64, len: i64) -> Vec<IntergenicRegion> {
+    regions
+        .into_iter()
+        .map(|mut region| {
+            if region.left.is_some() {
+                region.start += trim;
+            }
+            if region.right.is_some() {
+                region.end -= trim;
+            }
+            if region.start > len {
+                region.start -= len;
+                region.end -= len;
+            }
+            region
+        })
+        .filter(|region| region.start <= region.end)
+        .collect()
+}
+
+/// Only keeps the regions that are at least `min` and (if given) at most `max` nt long
+pub fn filter_by_length(
+    regions: Vec<IntergenicRegion>,
+    min: i64,
+    max: Option<i64>,
+) -> Vec<IntergenicRegion> {
+    regions
+        .into_iter()
+        .filter(|region| {
+            let length = region.end - region.start + 1;
+            length >= min && max.is_none_or(|max| length <= max)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gff::tests::entry;
+    use proptest::prelude::*;
+
+    fn coordinates(regions: &[IntergenicRegion]) -> Vec<(i64, i64)> {
+        regions.iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn merges_gene_mrna_cds_hierarchy() {
+        let gff = [
+            entry("gene", 100, 500, "+", "ID=gene1"),
+            entry("mRNA", 100, 500, "+", "ID=mrna1"),
+            entry("exon", 100, 200, "+", "ID=exon1"),
+            entry("CDS", 150, 200, "+", "ID=cds1"),
+            entry("exon", 300, 500, "+", "ID=exon2"),
+            entry("CDS", 300, 450, "+", "ID=cds2"),
+        ];
+        let refs: Vec<&GffEntry> = gff.iter().collect();
+        let blocks = merge_entries(&refs);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!((blocks[0].start, blocks[0].end), (100, 500));
+        assert_eq!(blocks[0].first.name(), "gene1");
+        assert_eq!(blocks[0].last.name(), "gene1");
+    }
+
+    #[test]
+    fn nested_feature_does_not_shorten_previous() {
+        // The CDS ends long before its gene does, which used to create an IGR inside the gene
+        let gff = vec![
+            entry("gene", 100, 1000, "+", "ID=gene1"),
+            entry("CDS", 200, 300, "+", "ID=cds1"),
+            entry("gene", 1200, 1500, "-", "ID=gene2"),
+        ];
+        let regions = get_intergenic_regions(&gff, 2000, 0, ".", &GenicTypes::default(), false);
+        assert_eq!(
+            coordinates(&regions),
+            vec![(1, 99), (1001, 1199), (1501, 2000)]
+        );
+        assert_eq!(regions[1].left.as_ref().unwrap().name(), "gene1");
+        assert_eq!(regions[1].right.as_ref().unwrap().name(), "gene2");
+    }
+
+    #[test]
+    fn overlapping_genes_are_merged() {
+        let gff = vec![
+            entry("gene", 100, 300, "+", "ID=gene1"),
+            entry("gene", 250, 600, "+", "ID=gene2"),
+            entry("gene", 700, 800, "+", "ID=gene3"),
+        ];
+        let regions = get_intergenic_regions(&gff, 800, 0, ".", &GenicTypes::default(), false);
+        assert_eq!(coordinates(&regions), vec![(1, 99), (601, 699)]);
+        assert_eq!(regions[1].left.as_ref().unwrap().name(), "gene2");
+    }
+
+    #[test]
+    fn flanks_are_clipped_to_the_region() {
+        let gff = vec![
+            entry("gene", 100, 200, "+", "ID=gene1"),
+            entry("gene", 231, 300, "-", "ID=gene2"),
+            entry("gene", 400, 500, "+", "ID=gene3"),
+        ];
+        let regions = get_intergenic_regions(&gff, 500, 0, ".", &GenicTypes::default(), false);
+        let flank = |flank_type| -> Vec<(String, i64, i64, String)> {
+            create_flanking_entries(&regions, "chr1", 50, flank_type, 500)
+                .into_iter()
+                .map(|f| (f.r#type, f.start, f.end, f.attributes.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            flank(FlankType::Upstream),
+            vec![
+                (
+                    "upstream_region".to_string(),
+                    50,
+                    99,
+                    "ID=gene1_upstream_region;Parent=gene1".to_string()
+                ),
+                (
+                    "upstream_region".to_string(),
+                    301,
+                    350,
+                    "ID=gene2_upstream_region;Parent=gene2".to_string()
+                ),
+                (
+                    "upstream_region".to_string(),
+                    350,
+                    399,
+                    "ID=gene3_upstream_region;Parent=gene3".to_string()
+                ),
+            ]
+        );
+        let utrs = flank(FlankType::Utr);
+        assert_eq!(utrs.len(), 5);
+        assert_eq!(
+            utrs[1],
+            (
+                "three_prime_UTR".to_string(),
+                201,
+                230,
+                "ID=gene1_three_prime_UTR;Parent=gene1".to_string()
+            )
+        );
+        assert_eq!(
+            utrs[2],
+            (
+                "three_prime_UTR".to_string(),
+                201,
+                230,
+                "ID=gene2_three_prime_UTR;Parent=gene2".to_string()
+            )
+        );
+
+        // The flank of the first gene in the region across the origin is moved to the start
+        let gff = vec![
+            entry("gene", 10, 20, "+", "ID=gene1"),
+            entry("gene", 30, 90, "-", "ID=gene2"),
+        ];
+        let regions = get_intergenic_regions(&gff, 100, 0, ".", &GenicTypes::default(), true);
+        let flanks = create_flanking_entries(&regions, "chr1", 5, FlankType::Utr, 100);
+        let flanks: Vec<(&str, i64, i64)> = flanks
+            .iter()
+            .map(|f| (f.attributes.get_first("ID").unwrap(), f.start, f.end))
+            .collect();
+        assert_eq!(
+            flanks,
+            vec![
+                ("gene1_three_prime_UTR", 21, 25),
+                ("gene2_three_prime_UTR", 25, 29),
+                ("gene2_five_prime_UTR", 91, 95),
+                ("gene1_five_prime_UTR", 5, 9),
+            ]
+        );
+    }
+
+    #[test]
+    fn unsorted_input_gives_same_regions() {
+        let sorted = vec![
+            entry("gene", 10, 20, "+", "ID=gene1"),
+            entry("CDS", 12, 18, "+", "ID=cds1"),
+            entry("gene", 40, 50, "+", "ID=gene2"),
+        ];
+        let mut unsorted = sorted.clone();
+        unsorted.reverse();
+        assert_eq!(
+            coordinates(&get_intergenic_regions(
+                &sorted,
+                60,
+                0,
+                ".",
+                &GenicTypes::default(),
+                false
+            )),
+            coordinates(&get_intergenic_regions(
+                &unsorted,
+                60,
+                0,
+                ".",
+                &GenicTypes::default(),
+                false
+            ))
+        );
+    }
+
+    #[test]
+    fn genic_types_define_genic_space() {
+        let gff = vec![
+            entry("region", 1, 100, "+", "ID=chr1"),
+            entry("gene", 10, 20, "+", "ID=gene1"),
+            entry("ncRNA", 30, 40, "+", "ID=ncrna1"),
+            entry("repeat_region", 50, 60, "+", "ID=repeat1"),
+            entry("gene", 70, 80, "+", "ID=gene2"),
+        ];
+        let only_genes = GenicTypes {
+            include: Some(vec!["gene".to_string()]),
+            exclude: Vec::new(),
+        };
+        assert_eq!(
+            coordinates(&get_intergenic_regions(
+                &gff,
+                100,
+                0,
+                ".",
+                &only_genes,
+                false
+            )),
+            vec![(1, 9), (21, 69), (81, 100)]
+        );
+        let without_ncrna = GenicTypes {
+            include: None,
+            exclude: vec!["ncRNA".to_string()],
+        };
+        assert_eq!(
+            coordinates(&get_intergenic_regions(
+                &gff,
+                100,
+                0,
+                ".",
+                &without_ncrna,
+                false
+            )),
+            vec![(1, 9), (21, 49), (61, 69), (81, 100)]
+        );
+    }
+
+    #[test]
+    fn circular_regions_span_the_origin() {
+        let gff = vec![
+            entry("gene", 10, 20, "+", "ID=gene1"),
+            entry("gene", 30, 90, "-", "ID=gene2"),
+        ];
+        let regions = get_intergenic_regions(&gff, 100, 0, ".", &GenicTypes::default(), true);
+        assert_eq!(coordinates(&regions), vec![(21, 29), (91, 109)]);
+        assert_eq!(regions[1].left.as_ref().unwrap().name(), "gene2");
+        assert_eq!(regions[1].right.as_ref().unwrap().name(), "gene1");
+
+        // A gene spanning the origin leaves only the region after its wrapped end
+        let gff = vec![
+            entry("gene", 5, 20, "+", "ID=gene1"),
+            entry("gene", 90, 103, "+", "ID=gene2"),
+        ];
+        let regions = get_intergenic_regions(&gff, 100, 0, ".", &GenicTypes::default(), true);
+        assert_eq!(coordinates(&regions), vec![(4, 4), (21, 89)]);
+        assert_eq!(regions[0].left.as_ref().unwrap().name(), "gene2");
+
+        // Its wrapped end may reach past the first genes
+        let gff = vec![
+            entry("gene", 1, 5, "+", "ID=gene1"),
+            entry("gene", 90, 110, "+", "ID=gene2"),
+        ];
+        let regions = get_intergenic_regions(&gff, 100, 0, ".", &GenicTypes::default(), true);
+        assert_eq!(coordinates(&regions), vec![(11, 89)]);
+        assert_eq!(regions[0].left.as_ref().unwrap().name(), "gene2");
+        assert_eq!(regions[0].right.as_ref().unwrap().name(), "gene2");
+    }
+
+    #[test]
+    fn trimming_only_shrinks_sides_next_to_genes() {
+        let gff = vec![
+            entry("gene", 20, 30, "+", "ID=gene1"),
+            entry("gene", 45, 60, "+", "ID=gene2"),
+        ];
+        let regions = get_intergenic_regions(&gff, 100, 0, ".", &GenicTypes::default(), false);
+        let trimmed = trim_regions(regions, 5, 100);
+        assert_eq!(coordinates(&trimmed), vec![(1, 14), (36, 39), (66, 100)]);
+        assert_eq!(
+            coordinates(&filter_by_length(trimmed.clone(), 5, None)),
+            vec![(1, 14), (66, 100)]
+        );
+        assert_eq!(
+            coordinates(&filter_by_length(trimmed, 1, Some(14))),
+            vec![(1, 14), (36, 39)]
+        );
+
+        // Trimmed to after the origin of a circular sequence
+        let gff = vec![entry("gene", 10, 95, "+", "ID=gene1")];
+        let regions = get_intergenic_regions(&gff, 100, 0, ".", &GenicTypes::default(), true);
+        assert_eq!(coordinates(&regions), vec![(96, 109)]);
+        assert_eq!(coordinates(&trim_regions(regions, 5, 100)), vec![(1, 4)]);
+    }
+
+    #[test]
+    fn ids_are_stable_and_unique() {
+        let mut gene1 = entry("gene", 10, 20, "+", "ID=gene1");
+        gene1
+            .attributes
+            .insert("locus_tag", vec!["b0001".to_string()]);
+        let gff = vec![gene1, entry("gene", 30, 40, "-", "ID=gene2")];
+        let regions = get_intergenic_regions(&gff, 50, 0, ".", &GenicTypes::default(), false);
+
+        let ids = |template: &str| {
+            create_intergenic_entries(
+                regions.clone(),
+                "chr1".to_string(),
+                ".",
+                template,
+                &mut HashMap::new(),
+            )
+            .iter()
+            .map(|e| e.attributes.get_first("ID").unwrap().to_string())
+            .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            ids(DEFAULT_ID_TEMPLATE),
+            vec!["IGR_chr1_1_9", "IGR_chr1_21_29", "IGR_chr1_41_50"]
+        );
+        assert_eq!(
+            ids("IGR_{left}_{right}_{strand}"),
+            vec![
+                "IGR_none_b0001_both",
+                "IGR_b0001_gene2_both",
+                "IGR_gene2_none_both"
+            ]
+        );
+        assert_eq!(ids("IGR"), vec!["IGR", "IGR_2", "IGR_3"]);
+    }
+
+    #[test]
+    fn orientation_follows_the_flanking_strands() {
+        assert_eq!(
+            Orientation::from_strands("+", "-"),
+            Some(Orientation::Convergent)
+        );
+        assert_eq!(
+            Orientation::from_strands("-", "+"),
+            Some(Orientation::Divergent)
+        );
+        assert_eq!(
+            Orientation::from_strands("+", "+"),
+            Some(Orientation::CodirectionalForward)
+        );
+        assert_eq!(
+            Orientation::from_strands("-", "-"),
+            Some(Orientation::CodirectionalReverse)
+        );
+        assert_eq!(Orientation::from_strands("+", "."), None);
+        assert_eq!(Orientation::from_strands("?", "-"), None);
+    }
+
+    #[test]
+    fn intergenic_entries_name_flanks_and_orientation() {
+        let gff = vec![
+            entry("gene", 10, 20, "+", "ID=gene1"),
+            entry("gene", 30, 40, "-", "ID=gene2"),
+            entry("gene", 50, 60, "+", "ID=gene3"),
+            entry("gene", 70, 80, "+", "ID=gene4"),
+            entry("gene", 90, 100, "-", "ID=gene5"),
+            entry("gene", 110, 120, "-", "ID=gene6"),
+            entry("gene", 130, 140, ".", "ID=gene7"),
+        ];
+        let regions = get_intergenic_regions(&gff, 150, 0, ".", &GenicTypes::default(), false);
+        let entries = create_intergenic_entries(
+            regions,
+            "chr1".to_string(),
+            ".",
+            DEFAULT_ID_TEMPLATE,
+            &mut HashMap::new(),
+        );
+        let flanks: Vec<(Option<&str>, Option<&str>, Option<&str>)> = entries
+            .iter()
+            .map(|e| {
+                (
+                    e.attributes.get_first("left_gene"),
+                    e.attributes.get_first("right_gene"),
+                    e.attributes.get_first("orientation"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            flanks,
+            vec![
+                (None, Some("gene1"), None),
+                (Some("gene1"), Some("gene2"), Some("convergent")),
+                (Some("gene2"), Some("gene3"), Some("divergent")),
+                (Some("gene3"), Some("gene4"), Some("codirectional_forward")),
+                (Some("gene4"), Some("gene5"), Some("convergent")),
+                (Some("gene5"), Some("gene6"), Some("codirectional_reverse")),
+                (Some("gene6"), Some("gene7"), None),
+                (Some("gene7"), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn adjacent_entries_leave_no_region() {
+        let gff = vec![
+            entry("gene", 1, 10, "+", "ID=gene1"),
+            entry("gene", 11, 20, "+", "ID=gene2"),
+        ];
+        assert!(get_intergenic_regions(&gff, 20, 0, ".", &GenicTypes::default(), false).is_empty());
+    }
+
+    #[test]
+    fn stranded_regions_ignore_other_strand() {
+        let gff = vec![
+            entry("gene", 10, 20, "+", "ID=gene1"),
+            entry("gene", 30, 40, "-", "ID=gene2"),
+            entry("gene", 50, 60, "+", "ID=gene3"),
+        ];
+        let regions = get_intergenic_regions(&gff, 70, 0, "+", &GenicTypes::default(), false);
+        assert_eq!(coordinates(&regions), vec![(1, 9), (21, 49), (61, 70)]);
+    }
+
+    /// Random entries (start, length, strand) on a linear or circular sequence of random length
+    /// On circular sequences entries may end past the end, spanning the origin
+    fn sequence_strategy() -> impl Strategy<Value = (bool, i64, Vec<GffEntry>)> {
+        (any::<bool>(), 1i64..500).prop_flat_map(|(circular, len)| {
+            let entries = prop::collection::vec(
+                (1..=len, 1i64..60, prop::sample::select(vec!["+", "-", "."])),
+                0..20,
+            )
+            .prop_map(move |entries| {
+                entries
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, (start, length, strand))| {
+                        // Spanning the origin at most once
+                        let end = match circular {
+                            true => start + length.min(len) - 1,
+                            false => (start + length - 1).min(len),
+                        };
+                        entry("gene", start, end, strand, &format!("ID=gene{}", idx))
+                    })
+                    .collect::<Vec<GffEntry>>()
+            });
+            (Just(circular), Just(len), entries)
+        })
+    }
+
+    /// Whether the base is covered by the entry or region, also by its part past the origin
+    fn covers(start: i64, end: i64, base: i64, len: i64) -> bool {
+        (start <= base && base <= end) || (start <= base + len && base + len <= end)
+    }
+
+    /// For every base of the sequence, how many genic entries and intergenic regions cover it
+    fn coverage(len: i64, gff: &[GffEntry], regions: &[IntergenicRegion]) -> Vec<(usize, usize)> {
+        (1..=len)
+            .map(|base| {
+                let genic = gff
+                    .iter()
+                    .filter(|e| covers(e.start, e.end, base, len))
+                    .count();
+                let intergenic = regions
+                    .iter()
+                    .filter(|r| covers(r.start, r.end, base, len))
+                    .count();
+                (genic, intergenic)
+            })
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn regions_are_within_sequence((circular, len, gff) in sequence_strategy(), buffer in 0i64..10) {
+            for region in get_intergenic_regions(&gff, len, buffer, ".", &GenicTypes::default(), circular) {
+                prop_assert!(1 <= region.start && region.start <= region.end && region.start <= len);
+                // Only the region across the origin of a circular sequence ends past the end
+                prop_assert!(region.end <= len || (circular && region.end - len < region.start));
+            }
+        }
+
+        #[test]
+        fn regions_never_overlap_genic_bases((circular, len, gff) in sequence_strategy(), buffer in 0i64..10) {
+            let regions = get_intergenic_regions(&gff, len, buffer, ".", &GenicTypes::default(), circular);
+            for (genic, intergenic) in coverage(len, &gff, &regions) {
+                prop_assert!(genic == 0 || intergenic == 0);
+                prop_assert!(intergenic <= 1);
+            }
+        }
+
+        #[test]
+        fn regions_and_genes_tile_sequence((circular, len, gff) in sequence_strategy()) {
+            let regions = get_intergenic_regions(&gff, len, 0, ".", &GenicTypes::default(), circular);
+            for (genic, intergenic) in coverage(len, &gff, &regions) {
+                prop_assert!(genic > 0 || intergenic == 1);
+            }
+        }
+
+        #[test]
+        fn stranded_regions_never_overlap_genes_on_that_strand((circular, len, gff) in sequence_strategy()) {
+            for strand in ["+", "-"] {
+                let regions = get_intergenic_regions(&gff, len, 0, strand, &GenicTypes::default(), circular);
+                let on_strand: Vec<GffEntry> = gff
+                    .iter()
+                    .filter(|e| e.strand == strand || e.strand == ".")
+                    .cloned()
+                    .collect();
+                for (genic, intergenic) in coverage(len, &on_strand, &regions) {
+                    prop_assert!((genic == 0) == (intergenic == 1));
+                }
+            }
+        }
+
+        #[test]
+        fn trimmed_regions_stay_within_untrimmed((circular, len, gff) in sequence_strategy(), trim in 0i64..10) {
+            let regions = get_intergenic_regions(&gff, len, 0, ".", &GenicTypes::default(), circular);
+            for trimmed in trim_regions(regions.clone(), trim, len) {
+                // Regions trimmed to after the origin were moved to the start of the sequence
+                let within = |shift: i64| {
+                    regions
+                        .iter()
+                        .any(|r| r.start <= trimmed.start + shift && trimmed.end + shift <= r.end)
+                };
+                prop_assert!(within(0) || within(len));
+                prop_assert!(1 <= trimmed.start && trimmed.start <= trimmed.end && trimmed.start <= len);
+                prop_assert!(trimmed.end <= len || (circular && trimmed.end - len < trimmed.start));
+            }
+        }
+    }
+}
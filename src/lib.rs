@@ -0,0 +1,23 @@
+//! Shared models and parsers used by the intergene-finder, depth-add-name and csv_add binaries.
+//!
+//! - [`gff`]: GFF entries, parsing and writing
+//! - [`fasta`]: FASTA records, parsing and writing
+//! - [`bed`]: BED regions
+//! - [`compression`]: reading gzip/bgzip compressed files, writing bgzip
+//! - [`error`]: the error type shared by all modules
+//! - [`depth`]: `samtools depth` records and naming them by BED region
+//! - [`hierarchy`]: the parent/child relationships between GFF entries, spliced sequences
+//! - [`intervals`]: finding the intergenic regions between GFF entries
+//! - [`translate`]: translating coding sequences with the NCBI genetic codes
+//! - [`validate`]: checking an annotation against its sequences
+
+pub mod bed;
+pub mod compression;
+pub mod depth;
+pub mod error;
+pub mod fasta;
+pub mod gff;
+pub mod hierarchy;
+pub mod intervals;
+pub mod translate;
+pub mod validate;
@@ -0,0 +1,276 @@
+use crate::compression;
+use crate::error::IntergeneError;
+use crate::fasta::format_header;
+use crate::gff::GffEntry;
+use std::fmt::Write as _;
+
+/// An NCBI genetic code (translation table)
+/// The amino acids are given for all 64 codons in the NCBI order, i.e TTT, TTC, TTA, TTG, TCT, ...
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct GeneticCode {
+    pub id: u8,
+    pub amino_acids: &'static str,
+    pub starts: &'static [&'static str],
+}
+
+/// The genetic codes that do not depend on the context of a codon (i.e all but 27, 28 and 31)
+const GENETIC_CODES: [GeneticCode; 23] = [
+    GeneticCode {
+        id: 1,
+        amino_acids: "FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["TTG", "CTG", "ATG"],
+    },
+    GeneticCode {
+        id: 2,
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG",
+        starts: &["ATT", "ATC", "ATA", "ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 3,
+        amino_acids: "FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["ATA", "ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 4,
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["TTA", "TTG", "CTG", "ATT", "ATC", "ATA", "ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 5,
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG",
+        starts: &["TTG", "ATT", "ATC", "ATA", "ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 6,
+        amino_acids: "FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["ATG"],
+    },
+    GeneticCode {
+        id: 9,
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: &["ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 10,
+        amino_acids: "FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["ATG"],
+    },
+    GeneticCode {
+        id: 11,
+        amino_acids: "FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["TTG", "CTG", "ATT", "ATC", "ATA", "ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 12,
+        amino_acids: "FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["CTG", "ATG"],
+    },
+    GeneticCode {
+        id: 13,
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG",
+        starts: &["TTG", "ATA", "ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 14,
+        amino_acids: "FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: &["ATG"],
+    },
+    GeneticCode {
+        id: 16,
+        amino_acids: "FFLLSSSSYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["ATG"],
+    },
+    GeneticCode {
+        id: 21,
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: &["ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 22,
+        amino_acids: "FFLLSS*SYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["ATG"],
+    },
+    GeneticCode {
+        id: 23,
+        amino_acids: "FF*LSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["ATT", "ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 24,
+        amino_acids: "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        starts: &["TTG", "CTG", "ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 25,
+        amino_acids: "FFLLSSSSYY**CCGWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["TTG", "ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 26,
+        amino_acids: "FFLLSSSSYY**CC*WLLLAPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["CTG", "ATG"],
+    },
+    GeneticCode {
+        id: 29,
+        amino_acids: "FFLLSSSSYYYYCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["ATG"],
+    },
+    GeneticCode {
+        id: 30,
+        amino_acids: "FFLLSSSSYYEECC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["ATG"],
+    },
+    GeneticCode {
+        id: 32,
+        amino_acids: "FFLLSSSSYY*WCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: &["TTG", "CTG", "ATT", "ATC", "ATA", "ATG", "GTG"],
+    },
+    GeneticCode {
+        id: 33,
+        amino_acids: "FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        starts: &["TTG", "CTG", "ATG", "GTG"],
+    },
+];
+
+impl GeneticCode {
+    /// The NCBI genetic code with the given id, e.g 11 for bacteria or 4 for mycoplasma
+    pub fn from_id(id: u8) -> Option<GeneticCode> {
+        GENETIC_CODES.iter().find(|code| code.id == id).copied()
+    }
+
+    /// The amino acid a codon codes for, 'X' for codons with ambiguous bases
+    pub fn translate_codon(&self, codon: &[u8]) -> char {
+        let mut idx = 0;
+        for base in codon {
+            let value = match base.to_ascii_uppercase() {
+                b'T' | b'U' => 0,
+                b'C' => 1,
+                b'A' => 2,
+                b'G' => 3,
+                _ => return 'X',
+            };
+            idx = idx * 4 + value;
+        }
+        self.amino_acids.as_bytes()[idx] as char
+    }
+
+    pub fn is_start(&self, codon: &[u8]) -> bool {
+        let codon = String::from_utf8_lossy(codon)
+            .to_ascii_uppercase()
+            .replace('U', "T");
+        self.starts.contains(&codon.as_str())
+    }
+}
+
+/// A translated sequence, along with how many stop codons were found before its end
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Protein {
+    pub seq: String,
+    pub internal_stops: usize,
+}
+
+/// Translates a (5' -> 3') coding sequence, skipping the first `phase` bases
+/// If the translation starts at the first base, an alternative start codon is translated as M.
+/// A trailing stop codon is left out, stops before it are kept as '*' and counted
+pub fn translate(seq: &str, code: &GeneticCode, phase: usize) -> Protein {
+    let bases = seq.as_bytes().get(phase..).unwrap_or(&[]);
+    let mut protein: String = bases
+        .chunks_exact(3)
+        .enumerate()
+        .map(|(idx, codon)| {
+            if idx == 0 && phase == 0 && code.is_start(codon) {
+                'M'
+            } else {
+                code.translate_codon(codon)
+            }
+        })
+        .collect();
+    if protein.ends_with('*') {
+        protein.pop();
+    }
+    Protein {
+        internal_stops: protein.matches('*').count(),
+        seq: protein,
+    }
+}
+
+/// Translates the entries of the given type (e.g CDS) and writes them as a protein FASTA
+/// The genetic code is taken from the `transl_table` attribute of an entry, or `default_code`
+/// Proteins with internal stop codons get `internal_stops=N` added to their header
+pub fn write_protein_fasta(
+    entry_type: &str,
+    gff_entries: &[GffEntry],
+    filename: &str,
+    header_template: &str,
+    default_code: &GeneticCode,
+    bgzip: bool,
+) -> Result<(), IntergeneError> {
+    let mut to_write = String::new();
+    for entry in gff_entries.iter().filter(|e| e.r#type == entry_type) {
+        let code = match entry.attributes.get_first("transl_table") {
+            Some(table) => table
+                .parse::<u8>()
+                .ok()
+                .and_then(GeneticCode::from_id)
+                .ok_or_else(|| IntergeneError::InvalidAttribute {
+                    entry: entry.name(),
+                    key: "transl_table".to_string(),
+                    value: table.to_string(),
+                })?,
+            None => *default_code,
+        };
+        let phase = entry.phase.parse::<usize>().unwrap_or(0);
+        let protein = translate(&entry.seq, &code, phase);
+
+        let _ = write!(to_write, ">{}", format_header(header_template, entry));
+        if protein.internal_stops > 0 {
+            let _ = write!(to_write, " internal_stops={}", protein.internal_stops);
+        }
+        to_write.push('\n');
+        for line in protein.seq.as_bytes().chunks(80) {
+            let _ = writeln!(to_write, "{}", String::from_utf8_lossy(line));
+        }
+    }
+    compression::write_file(filename, &to_write, bgzip)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_with_alternative_start_and_trailing_stop() {
+        let code = GeneticCode::from_id(11).unwrap();
+        let protein = translate("GTGAAATTTTAA", &code, 0);
+        assert_eq!(protein.seq, "MKF");
+        assert_eq!(protein.internal_stops, 0);
+    }
+
+    #[test]
+    fn counts_internal_stops_and_ambiguous_codons() {
+        let code = GeneticCode::from_id(11).unwrap();
+        let protein = translate("ATGTGAANNTGGTAG", &code, 0);
+        assert_eq!(protein.seq, "M*XW");
+        assert_eq!(protein.internal_stops, 1);
+    }
+
+    #[test]
+    fn mycoplasma_code_reads_tga_as_tryptophan() {
+        let code = GeneticCode::from_id(4).unwrap();
+        assert_eq!(translate("ATGTGATAA", &code, 0).seq, "MW");
+    }
+
+    #[test]
+    fn balanophoraceae_plastid_code_reads_tag_as_tryptophan() {
+        let code = GeneticCode::from_id(32).unwrap();
+        assert_eq!(translate("ATGTAGTGATAA", &code, 0).seq, "MW*");
+    }
+
+    #[test]
+    fn phase_skips_leading_bases() {
+        let code = GeneticCode::from_id(1).unwrap();
+        // The first codon after skipping is not a start codon, so TTG stays L
+        assert_eq!(translate("AATTGAAA", &code, 2).seq, "LK");
+    }
+}
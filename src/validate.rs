@@ -0,0 +1,333 @@
+//! Checking an annotation against its sequences, i.e the `--validate` mode of intergene-finder
+//!
+//! Every entry is checked for a known sequence and valid coordinates within it, and (if the
+//! sequence is given) for bases other than ACGTN. The CDS lines sharing a parent (`transcript_id`
+//! in GTF) or ID are checked together as one coding sequence: its length has to be a multiple of
+//! 3, it has to start with a start codon and end with a stop codon of its genetic code. As GTF CDS
+//! leave out the stop codon, `stop_codon` features outside the CDS lines count as part of it.
+//! Entries marked with `partial=true` are not checked for start/stop codons.
+
+use crate::compression;
+use crate::error::IntergeneError;
+use crate::fasta::{add_seq_to_entries, SeqSource};
+use crate::gff::GffEntry;
+use crate::translate::GeneticCode;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+
+/// Something wrong with an entry
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Issue {
+    /// The sequence of the entry is not in the FASTA file (or sequence lengths)
+    UnknownSeqid,
+    /// Not 1 <= start <= end
+    InvalidCoordinates,
+    /// The entry ends past the end of the sequence (and does not span the origin of a circular one)
+    OutOfBounds { length: i64 },
+    /// The length of the coding sequence (without the phase) is not a multiple of 3
+    CdsLength(usize),
+    /// The coding sequence starts with the given codon, which is no start codon
+    NoStartCodon(String),
+    /// The coding sequence ends with the given codon, which is no stop codon
+    NoStopCodon(String),
+    /// The sequence of the entry contains the given characters other than ACGTN
+    InvalidBases(String),
+}
+
+impl Issue {
+    /// A short name of the kind of issue, e.g for filtering the report
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Issue::UnknownSeqid => "unknown_seqid",
+            Issue::InvalidCoordinates => "invalid_coordinates",
+            Issue::OutOfBounds { .. } => "out_of_bounds",
+            Issue::CdsLength(_) => "cds_length",
+            Issue::NoStartCodon(_) => "no_start_codon",
+            Issue::NoStopCodon(_) => "no_stop_codon",
+            Issue::InvalidBases(_) => "invalid_bases",
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::UnknownSeqid => write!(f, "the sequence is unknown"),
+            Issue::InvalidCoordinates => write!(f, "start has to be >= 1 and <= end"),
+            Issue::OutOfBounds { length } => write!(f, "the sequence is {} bases long", length),
+            Issue::CdsLength(length) => write!(f, "{} bases are no multiple of 3", length),
+            Issue::NoStartCodon(codon) => write!(f, "{} is no start codon", codon),
+            Issue::NoStopCodon(codon) => write!(f, "{} is no stop codon", codon),
+            Issue::InvalidBases(bases) => write!(f, "contains {}", bases),
+        }
+    }
+}
+
+/// An issue along with the entry it was found in
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Finding {
+    pub seqid: String,
+    pub start: i64,
+    pub end: i64,
+    pub r#type: String,
+    pub name: String,
+    pub issue: Issue,
+}
+
+impl Finding {
+    fn new(entry: &GffEntry, issue: Issue) -> Finding {
+        Finding {
+            seqid: entry.seqid.clone(),
+            start: entry.start,
+            end: entry.end,
+            r#type: entry.r#type.clone(),
+            name: entry.name(),
+            issue,
+        }
+    }
+}
+
+/// Checks the entries of a single sequence (see the module documentation)
+/// `length` is the length of the sequence, None if it is unknown. Its bases are only checked if
+/// `refseq` is given. CDS use the genetic code of their `transl_table` attribute or `default_code`
+pub fn validate_entries(
+    entries: &[GffEntry],
+    length: Option<i64>,
+    refseq: Option<&dyn SeqSource>,
+    default_code: &GeneticCode,
+    circular: bool,
+) -> Result<Vec<Finding>, IntergeneError> {
+    let mut findings: Vec<Finding> = Vec::new();
+    let mut valid: Vec<GffEntry> = Vec::new();
+    for entry in entries {
+        let length = match length {
+            Some(length) => length,
+            None => {
+                findings.push(Finding::new(entry, Issue::UnknownSeqid));
+                continue;
+            }
+        };
+        if entry.start < 1 || entry.start > entry.end {
+            findings.push(Finding::new(entry, Issue::InvalidCoordinates));
+        } else if entry.end > length
+            && !(circular && entry.start <= length && entry.end - length < entry.start)
+        {
+            findings.push(Finding::new(entry, Issue::OutOfBounds { length }));
+        } else {
+            valid.push(entry.clone());
+        }
+    }
+    let refseq = match refseq {
+        Some(refseq) => refseq,
+        None => return Ok(findings),
+    };
+
+    // Only the entries within the sequence get their sequence
+    add_seq_to_entries(&mut valid, refseq, 0, circular)?;
+    for entry in &valid {
+        let mut invalid: Vec<char> = entry
+            .seq
+            .chars()
+            .filter(|base| !matches!(base.to_ascii_uppercase(), 'A' | 'C' | 'G' | 'T' | 'N'))
+            .collect();
+        invalid.sort_unstable();
+        invalid.dedup();
+        if !invalid.is_empty() {
+            let invalid = invalid.into_iter().collect();
+            findings.push(Finding::new(entry, Issue::InvalidBases(invalid)));
+        }
+    }
+
+    // The CDS (and stop codon) lines of one coding sequence share a parent (or an ID)
+    let mut cds: HashMap<&str, Vec<&GffEntry>> = HashMap::new();
+    let mut order: Vec<&str> = Vec::new();
+    for entry in valid
+        .iter()
+        .filter(|e| e.r#type == "CDS" || e.r#type == "stop_codon")
+    {
+        let key = entry
+            .parent_ids()
+            .first()
+            .copied()
+            .or_else(|| entry.attributes.get_first("ID"))
+            .unwrap_or(entry.seqid.as_str());
+        if !cds.contains_key(key) {
+            order.push(key);
+        }
+        cds.entry(key).or_default().push(entry);
+    }
+    for key in order {
+        let parts = cds.remove(key).unwrap_or_default();
+        let (coding, stops): (Vec<&GffEntry>, Vec<&GffEntry>) =
+            parts.into_iter().partition(|part| part.r#type == "CDS");
+        let mut parts = coding;
+        if parts.is_empty() {
+            continue;
+        }
+        // CDS without parent or ID can not be joined with others
+        if parts[0].parent_ids().is_empty() && parts[0].attributes.get_first("ID").is_none() {
+            for part in parts {
+                findings.extend(check_cds(&[part], default_code));
+            }
+            continue;
+        }
+        // Stop codons that are not part of the CDS lines already (as in GTF)
+        let within_cds = |stop: &GffEntry| {
+            parts
+                .iter()
+                .any(|part| part.start <= stop.start && stop.end <= part.end)
+        };
+        let stops: Vec<&GffEntry> = stops.into_iter().filter(|stop| !within_cds(stop)).collect();
+        parts.extend(stops);
+        parts.sort_by_key(|part| part.start);
+        if parts[0].strand == "-" {
+            parts.reverse();
+        }
+        findings.extend(check_cds(&parts, default_code));
+    }
+    Ok(findings)
+}
+
+/// Checks the length and start/stop codons of the CDS lines (in transcription order) of a single
+/// coding sequence, the findings are reported for the first of them
+fn check_cds(parts: &[&GffEntry], default_code: &GeneticCode) -> Vec<Finding> {
+    let first = parts[0];
+    let seq: String = parts.iter().map(|part| part.seq.as_str()).collect();
+    let phase = first.phase.parse::<usize>().unwrap_or(0);
+    let seq = seq.get(phase..).unwrap_or("").as_bytes();
+
+    let mut findings: Vec<Finding> = Vec::new();
+    if seq.len() % 3 != 0 {
+        findings.push(Finding::new(first, Issue::CdsLength(seq.len())));
+    }
+    if first.attributes.get_first("partial") == Some("true") || seq.len() < 3 {
+        return findings;
+    }
+    let code = first
+        .attributes
+        .get_first("transl_table")
+        .and_then(|table| table.parse::<u8>().ok())
+        .and_then(GeneticCode::from_id)
+        .unwrap_or(*default_code);
+    let start = &seq[..3];
+    if !code.is_start(start) {
+        let codon = String::from_utf8_lossy(start).to_string();
+        findings.push(Finding::new(first, Issue::NoStartCodon(codon)));
+    }
+    let stop = &seq[seq.len() - seq.len() % 3 - 3..seq.len() - seq.len() % 3];
+    if code.translate_codon(stop) != '*' {
+        let codon = String::from_utf8_lossy(stop).to_string();
+        findings.push(Finding::new(first, Issue::NoStopCodon(codon)));
+    }
+    findings
+}
+
+/// Writes the findings as a tab separated report, one issue per line
+pub fn write_report(
+    findings: &[Finding],
+    filename: &str,
+    bgzip: bool,
+) -> Result<(), IntergeneError> {
+    let mut to_write = String::from("#seqid\tstart\tend\ttype\tname\tissue\tdetails\n");
+    for finding in findings {
+        let _ = writeln!(
+            to_write,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            finding.seqid,
+            finding.start,
+            finding.end,
+            finding.r#type,
+            finding.name,
+            finding.issue.kind(),
+            finding.issue
+        );
+    }
+    compression::write_file(filename, &to_write, bgzip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fasta::Seq;
+    use crate::gff::tests::entry;
+
+    fn kinds(findings: &[Finding]) -> Vec<(&str, &str)> {
+        findings
+            .iter()
+            .map(|f| (f.name.as_str(), f.issue.kind()))
+            .collect()
+    }
+
+    #[test]
+    fn reports_instead_of_panicking() {
+        // ATG AAA TAA | ATG AA | reverse complement of ATG CCC TGA
+        let refseq = Seq {
+            header: ">chr1".to_string(),
+            seq: "ATGAAATAAATGAARTCAGGGCAT".to_string(),
+        };
+        let code = GeneticCode::from_id(11).unwrap();
+        let entries = [
+            entry("CDS", 1, 9, "+", "ID=ok"),
+            entry("CDS", 10, 14, "+", "ID=short"),
+            entry("gene", 10, 16, "+", "ID=iupac"),
+            entry("CDS", 16, 24, "-", "ID=minus"),
+            entry("gene", 20, 30, "+", "ID=outside"),
+            entry("gene", 5, 4, "+", "ID=reversed"),
+        ];
+        let findings = validate_entries(&entries, Some(24), Some(&refseq), &code, false).unwrap();
+        assert_eq!(
+            kinds(&findings),
+            vec![
+                ("outside", "out_of_bounds"),
+                ("reversed", "invalid_coordinates"),
+                ("iupac", "invalid_bases"),
+                ("short", "cds_length"),
+                ("short", "no_stop_codon"),
+            ]
+        );
+        assert_eq!(findings[2].issue, Issue::InvalidBases("R".to_string()));
+
+        let findings = validate_entries(&entries, None, None, &code, false).unwrap();
+        assert!(findings.iter().all(|f| f.issue == Issue::UnknownSeqid));
+    }
+
+    #[test]
+    fn spliced_cds_is_checked_as_a_whole() {
+        let refseq = Seq {
+            header: ">chr1".to_string(),
+            seq: "ATGAACCCCCAATAG".to_string(),
+        };
+        let code = GeneticCode::from_id(11).unwrap();
+        let entries = [
+            entry("CDS", 1, 5, "+", "ID=cds1;Parent=mrna1"),
+            entry("CDS", 12, 15, "+", "ID=cds1;Parent=mrna1"),
+        ];
+        let findings = validate_entries(&entries, Some(15), Some(&refseq), &code, false).unwrap();
+        assert!(findings.is_empty(), "{:?}", findings);
+    }
+
+    #[test]
+    fn gtf_cds_is_checked_with_its_stop_codon() {
+        let refseq = Seq {
+            header: ">chr1".to_string(),
+            seq: "ATGAACCCCCAATAGATGAAATAA".to_string(),
+        };
+        let code = GeneticCode::from_id(11).unwrap();
+        let gtf = |r#type: &str, start: i64, end: i64, transcript: &str| {
+            let mut entry = entry(r#type, start, end, "+", ".");
+            let attributes = format!(r#"gene_id "g1"; transcript_id "{}";"#, transcript);
+            entry.attributes = crate::gff::Attributes::parse_gtf(&attributes).unwrap();
+            entry
+        };
+        let entries = [
+            gtf("CDS", 1, 5, "t1"),
+            gtf("CDS", 12, 12, "t1"),
+            gtf("stop_codon", 13, 15, "t1"),
+            // A stop codon within the CDS (as in GFF3) is not added twice
+            gtf("CDS", 16, 24, "t2"),
+            gtf("stop_codon", 22, 24, "t2"),
+        ];
+        let findings = validate_entries(&entries, Some(24), Some(&refseq), &code, false).unwrap();
+        assert!(findings.is_empty(), "{:?}", findings);
+    }
+}